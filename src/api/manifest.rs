@@ -0,0 +1,187 @@
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response, Sse},
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, oneshot};
+use tracing::{error, info};
+
+use crate::AppStateArc;
+use crate::api::progress_events;
+use crate::manifest::{
+    CacheOptions, FetchError, ManifestCache, clear_cached, diagnose_manifest,
+    fetch_and_filter_manifest, fetch_raw_manifest, list_cached, precache_channel_manifests,
+};
+use crate::ytdlp::NetworkOptions;
+
+#[derive(Serialize)]
+pub struct CachedManifest {
+    video_id: String,
+    // Unix seconds, parsed from the manifest's expire/ token
+    expires: u64,
+    fetched_at: u64,
+    valid: bool,
+}
+
+#[derive(Serialize)]
+pub struct RefreshedManifest {
+    video_id: String,
+    // Variant streams left after filtering
+    streams: usize,
+    expires: u64,
+}
+
+#[derive(Deserialize)]
+pub struct ClearCacheQuery {
+    // Clear only this video's manifest instead of the whole cache
+    id: Option<String>,
+}
+
+pub async fn list_cache(State(state): State<AppStateArc>) -> Json<Vec<CachedManifest>> {
+    let config = state.config.read().await;
+    let cache_dir = config.jellyfin_media_path.join("manifests");
+
+    let manifests = list_cached(&cache_dir)
+        .into_iter()
+        .map(|cache| CachedManifest {
+            valid: cache.is_valid(config.max_manifest_cache_age_secs),
+            video_id: cache.video_id,
+            expires: cache.expires,
+            fetched_at: cache.fetched_at,
+        })
+        .collect();
+    Json(manifests)
+}
+
+pub async fn clear_cache(
+    State(state): State<AppStateArc>,
+    Query(query): Query<ClearCacheQuery>,
+) -> Response {
+    let cache_dir = state
+        .config
+        .read()
+        .await
+        .jellyfin_media_path
+        .join("manifests");
+
+    match clear_cached(&cache_dir, query.id.as_deref()) {
+        Ok(removed) => {
+            info!("Cleared {} manifest cache files", removed);
+            (StatusCode::OK, format!("Removed {} files", removed)).into_response()
+        }
+        Err(e) => {
+            error!("Failed to clear manifest cache: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+        }
+    }
+}
+
+/// Throws away a video's cached manifest and caches a freshly fetched one, for a
+/// manifest that is valid by its expiry but no longer plays. If the fetch fails the
+/// video is left uncached, so the next stream request tries again.
+pub async fn refresh_manifest(
+    State(state): State<AppStateArc>,
+    Path(video_id): Path<String>,
+) -> Response {
+    let (cache_dir, cache_options, network) = {
+        let config = state.config.read().await;
+        (
+            config.jellyfin_media_path.join("manifests"),
+            CacheOptions::from_config(&config),
+            NetworkOptions::from_config(&config),
+        )
+    };
+
+    if let Err(e) = clear_cached(&cache_dir, Some(&video_id)) {
+        error!("Failed to clear cached manifest for {}: {}", video_id, e);
+        return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
+    }
+
+    match fetch_and_filter_manifest(&video_id, &cache_dir, Some(cache_options), &None, &network)
+        .await
+    {
+        Ok(manifest) => {
+            let streams = manifest
+                .lines()
+                .filter(|line| line.starts_with("#EXT-X-STREAM-INF:"))
+                .count();
+            info!("Refreshed manifest for {}, {} streams", video_id, streams);
+            Json(RefreshedManifest {
+                expires: ManifestCache::new(&video_id, manifest).expires,
+                video_id,
+                streams,
+            })
+            .into_response()
+        }
+        Err(e)
+            if matches!(
+                e.downcast_ref::<FetchError>(),
+                Some(FetchError::RateLimited)
+            ) =>
+        {
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                [("Retry-After", "60")],
+                e.to_string(),
+            )
+                .into_response()
+        }
+        Err(e) => {
+            error!("Failed to refresh manifest for {}: {}", video_id, e);
+            (StatusCode::BAD_GATEWAY, e.to_string()).into_response()
+        }
+    }
+}
+
+/// Fetches and filters a video's manifest without caching it, and reports what
+/// the filter kept, for working out why a stream won't play.
+pub async fn debug_manifest(
+    State(state): State<AppStateArc>,
+    Path(video_id): Path<String>,
+) -> Response {
+    let network = NetworkOptions::from_config(&*state.config.read().await);
+
+    match fetch_raw_manifest(&video_id, &None, &network).await {
+        Ok(raw) => Json(diagnose_manifest(&video_id, raw)).into_response(),
+        Err(e) => {
+            error!("Failed to fetch manifest for {}: {}", video_id, e);
+            (StatusCode::BAD_GATEWAY, e.to_string()).into_response()
+        }
+    }
+}
+
+/// Caches the manifests of all of a channel's episodes ahead of playback, e.g.
+/// after adding a big channel with manifest maintenance off. Progress is streamed
+/// as SSE, in the same events as a sync's.
+pub async fn precache_channel(
+    State(state): State<AppStateArc>,
+    Path(id): Path<String>,
+) -> Response {
+    let settings = state.config.read().await.clone();
+    let Some(channel) = settings.channels.iter().find(|c| c.id == id).cloned() else {
+        return (StatusCode::NOT_FOUND, "Channel not found").into_response();
+    };
+
+    let (tx, rx) = mpsc::channel(100);
+    let (result_tx, result_rx) = oneshot::channel::<Option<String>>();
+    let shutdown = state.shutdown.clone();
+    state.tasks.spawn(async move {
+        info!("Precaching manifests of {}", channel.get_name());
+        let failure = match precache_channel_manifests(&channel, &settings, tx, &shutdown).await {
+            Ok(_) => None,
+            Err(e) => {
+                error!(
+                    "Failed to precache manifests of {}: {:#}",
+                    channel.get_name(),
+                    e
+                );
+                Some(format!("{:#}", e))
+            }
+        };
+        let _ = result_tx.send(failure);
+    });
+
+    Sse::new(progress_events(rx, result_rx)).into_response()
+}
@@ -1,8 +1,14 @@
 pub mod channels;
+pub mod manifest;
 pub mod playlist;
 pub mod settings;
+pub mod status;
+pub mod video;
+pub mod ws;
 
 use crate::AppStateArc;
+use crate::config::{Channel, MAX_CHECK_INTERVAL_MINUTES, SyncProgress, redact_credentials};
+use crate::library::strm_files;
 
 use axum::{
     Router,
@@ -12,8 +18,11 @@ use axum::{
 };
 use futures::{Stream, StreamExt, future, stream};
 use percent_encoding::percent_decode_str;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::time::SystemTime;
 use std::{borrow::Cow, convert::Infallible};
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, oneshot};
 use tokio_stream::wrappers::ReceiverStream;
 use tracing::{error, info};
 
@@ -28,7 +37,22 @@ pub fn routes() -> Router<AppStateArc> {
             "/config/check-interval",
             put(settings::update_check_interval),
         )
+        .route("/config/sync-window", put(settings::update_sync_window))
+        .route(
+            "/config/public-base-url",
+            put(settings::update_public_base_url),
+        )
+        .route(
+            "/config/sleep-intervals",
+            put(settings::update_sleep_intervals),
+        )
+        .route("/config/proxy", put(settings::update_proxy_url))
+        .route("/config/cookies-pool", put(settings::update_cookies_pool))
         .route("/config/media-path", put(settings::update_media_path))
+        .route("/config/export", get(settings::export_config))
+        .route("/config/import", post(settings::import_config))
+        .route("/fix-strm-urls", post(settings::fix_strm_urls))
+        .route("/maintenance/rewrite-strm", post(settings::rewrite_strm))
         .route(
             "/config/toggle-background-tasks",
             post(settings::toggle_background_tasks),
@@ -37,21 +61,126 @@ pub fn routes() -> Router<AppStateArc> {
             "/config/toggle-manifest-maintenance",
             post(settings::toggle_manifest_maintenance),
         )
+        .route(
+            "/config/toggle-dedup",
+            post(settings::toggle_dedup_across_channels),
+        )
+        .route("/filename-preview", post(settings::preview_filenames))
+        .route("/status", get(status::status))
+        .route("/status/view", get(status::status_view))
         // Channel routes
+        .route("/channels", get(channels::list_channels))
         .route("/channels/new", post(channels::create_channel))
+        .route("/channels/limits", put(channels::update_limits))
+        .route("/channels/repair", post(channels::repair_structures))
         .route("/channels/{id}", put(channels::update_channel))
         .route("/channels/{id}", delete(channels::delete_channel))
         .route("/channels/{id}/reset", post(channels::reset_channel))
         .route("/channels/{id}/progress-view", get(channels::progress_view))
+        .route("/channels/{id}/preview", get(channels::preview_channel))
+        .route("/channels/{id}/cancel", post(channels::cancel_sync))
+        .route("/channels/{id}/toggle-pause", post(channels::toggle_pause))
+        .route("/channels/{id}/images", get(channels::channel_images))
+        .route(
+            "/channels/{id}/images/{file}",
+            get(channels::channel_image_file),
+        )
+        .route(
+            "/channels/{id}/precache-manifests",
+            post(manifest::precache_channel),
+        )
+        .route(
+            "/channels/{id}/videos/{video_id}",
+            delete(video::delete_episode),
+        )
+        .route(
+            "/channels/{id}/rebuild-metadata",
+            post(channels::rebuild_metadata),
+        )
+        .route("/playlists", get(playlist::list_playlists))
         .route("/playlists/new", post(playlist::create_playlist))
         .route("/playlists/{id}", put(playlist::update_playlist))
         .route("/playlists/{id}", delete(playlist::delete_playlist))
         .route("/playlists/{id}/reset", post(playlist::reset_playlist))
+        .route("/playlists/{id}/cancel", post(channels::cancel_sync))
+        .route(
+            "/playlists/{id}/rebuild-metadata",
+            post(channels::rebuild_metadata),
+        )
         .route(
             "/playlists/{id}/progress-view",
             get(playlist::progress_view),
         )
+        .route("/video/{id}/reprocess", post(video::reprocess_video))
+        .route(
+            "/manifest/cache",
+            get(manifest::list_cache).delete(manifest::clear_cache),
+        )
+        .route("/manifest/{id}/debug", get(manifest::debug_manifest))
+        .route("/manifest/{id}/refresh", post(manifest::refresh_manifest))
         .route("/progress/{id}", get(progress_sse_handler))
+        .route("/progress/{id}/ws", get(ws::progress_ws_handler))
+}
+
+#[derive(Deserialize)]
+pub struct ResetQuery {
+    // Only rewind last_checked; existing episodes are skipped on the next sync
+    #[serde(default)]
+    pub keep_files: bool,
+}
+
+/// Splits a comma separated form value like "en, es" into its non-empty parts.
+pub(crate) fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|part| part.trim().to_string())
+        .filter(|part| !part.is_empty())
+        .collect()
+}
+
+/// A trimmed form value, or None when it was left blank.
+pub(crate) fn non_empty(value: &str) -> Option<String> {
+    let value = value.trim();
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+/// A check interval override form value in minutes, None when blank or 0.
+pub(crate) fn check_interval_override(minutes: Option<u64>) -> Result<Option<u64>, String> {
+    match minutes.filter(|minutes| *minutes > 0) {
+        Some(minutes) if minutes > MAX_CHECK_INTERVAL_MINUTES => Err(format!(
+            "Check interval must be at most {} minutes (a year)",
+            MAX_CHECK_INTERVAL_MINUTES
+        )),
+        minutes => Ok(minutes),
+    }
+}
+
+/// Rejects a geo proxy form value that isn't a URL; blank means no proxy.
+pub(crate) fn check_geo_proxy(value: &str) -> Result<(), String> {
+    match non_empty(value) {
+        Some(proxy) => url::Url::parse(&proxy)
+            .map(|_| ())
+            .map_err(|e| format!("Invalid geo proxy URL {}: {}", proxy, e)),
+        None => Ok(()),
+    }
+}
+
+/// A channel or playlist as listed by `/api/channels` and `/api/playlists`: its
+/// config with the times as RFC 3339 strings and proxy credentials replaced, plus
+/// the episodes on disk.
+pub(crate) fn channel_listing(channel: &Channel) -> Value {
+    let mut listing = json!(channel);
+    listing["geo_proxy_url"] = json!(channel.geo_proxy_url.as_deref().map(redact_credentials));
+    listing["last_checked"] = json!(rfc3339(channel.last_checked));
+    if let Some(result) = &channel.last_result {
+        listing["last_result"]["timestamp"] = json!(rfc3339(result.timestamp));
+    }
+    listing["video_count"] = json!(strm_files(&channel.media_dir).len());
+    listing
+}
+
+fn rfc3339(time: SystemTime) -> String {
+    chrono::DateTime::<chrono::Utc>::from(time).to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
 }
 
 async fn progress_sse_handler(
@@ -65,23 +194,12 @@ async fn progress_sse_handler(
     info!("Creating progress SSE handler for channel {}", decoded_id);
     let (tx, rx) = mpsc::channel(100);
     info!("Created channel with capacity 100");
-
-    let stream = ReceiverStream::new(rx)
-        .map(|msg| {
-            info!("Received message in stream: {}", msg);
-            // Send all regular messages as "message" events instead of "progress"
-            Ok(Event::default().data(msg))
-        })
-        .chain(stream::once(async {
-            info!("Sending completion message");
-            Ok(Event::default().event("complete").data("done"))
-        }))
-        .take_while(|msg| future::ready(msg.is_ok()));
+    // The sync's outcome, so a failure isn't reported as a clean completion
+    let (result_tx, result_rx) = oneshot::channel::<Option<String>>();
+    let stream = progress_events(rx, result_rx);
 
     // Get required config values
     let config = state.config.read().await;
-    let media_path = config.jellyfin_media_path.clone();
-    let server_addr = config.server_address.clone();
     let channel = config
         .channels
         .iter()
@@ -93,17 +211,256 @@ async fn progress_sse_handler(
     info!("Starting video processing task");
     // Spawn video loading task
     let state_clone = state.clone();
-    tokio::spawn(async move {
+    state.tasks.spawn(async move {
         info!("Processing videos for channel {}", channel.get_name());
-        if let Err(e) = channel
-            .process_new_videos(&media_path, &server_addr, &state_clone.config, Some(tx))
+        let failure = match channel
+            .process_new_videos(&state_clone.config, Some(tx), &state_clone.shutdown, None)
             .await
         {
-            error!("Error processing videos: {}", e);
-        }
+            Ok(_) => None,
+            Err(e) => {
+                error!("Error processing videos: {}", e);
+                Some(format!("{:#}", e))
+            }
+        };
+        let _ = result_tx.send(failure);
         info!("Finished processing videos");
     });
 
     info!("Returning SSE stream");
     Sse::new(stream)
 }
+
+/// SSE events for a task reporting to `rx`: each `SyncProgress` as a "message" event
+/// with its JSON, then an "error" event if `result` carries a failure, then "complete".
+pub(crate) fn progress_events(
+    rx: mpsc::Receiver<SyncProgress>,
+    result: oneshot::Receiver<Option<String>>,
+) -> impl Stream<Item = Result<Event, Infallible>> {
+    ReceiverStream::new(rx)
+        .map(|msg| {
+            info!("Received message in stream: {:?}", msg);
+            // Every sync message is a "message" event, its JSON `type` tells them apart
+            let data = serde_json::to_string(&msg).expect("sync progress serializes");
+            Ok(Event::default().data(data))
+        })
+        // The progress channel closes when the sync ends, by which point its result is in
+        .chain(
+            stream::once(async move {
+                result.await.ok().flatten().map(|message| {
+                    info!("Sending error message");
+                    Ok(Event::default().event("error").data(message))
+                })
+            })
+            .filter_map(future::ready),
+        )
+        .chain(stream::once(async {
+            info!("Sending completion message");
+            Ok(Event::default().event("complete").data("done"))
+        }))
+        .take_while(|msg| future::ready(msg.is_ok()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AppState;
+    use crate::config::Config;
+    use crate::templates::Templates;
+    use axum::body::{Body, to_bytes};
+    use axum::http::{Request, StatusCode};
+    use axum::response::IntoResponse;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::sync::RwLock;
+    use tokio_util::sync::CancellationToken;
+    use tokio_util::task::TaskTracker;
+    use tower::ServiceExt;
+
+    fn app() -> Router {
+        app_with(Config::default())
+    }
+
+    fn app_with(config: Config) -> Router {
+        let state = Arc::new(AppState {
+            config: Arc::new(RwLock::new(config)),
+            templates: Arc::new(Templates::new().unwrap()),
+            shutdown: CancellationToken::new(),
+            tasks: TaskTracker::new(),
+            ytdlp_error: None,
+            config_recovery: None,
+        });
+        Router::new().nest("/api", routes()).with_state(state)
+    }
+
+    async fn post(app: &Router, uri: &str) -> (StatusCode, String) {
+        let response = app
+            .clone()
+            .oneshot(Request::post(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    fn hx_post(html: &str) -> &str {
+        html.split("hx-post=\"")
+            .nth(1)
+            .and_then(|rest| rest.split('"').next())
+            .expect("the button posts somewhere")
+    }
+
+    // Saving writes config.json under the user's config directory
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn manifest_maintenance_button_posts_to_a_real_route() {
+        let dir = tempfile::tempdir().unwrap();
+        // SAFETY: no other test looks at XDG_CONFIG_HOME, and they only read the
+        // environment through std, which serializes access to it
+        unsafe { std::env::set_var("XDG_CONFIG_HOME", dir.path()) };
+        std::fs::create_dir_all(Config::config_dir()).unwrap();
+        let app = app();
+
+        let (status, button) = post(&app, "/api/config/toggle-manifest-maintenance").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(button.contains("Disable"));
+
+        // Clicking the returned button again toggles it back
+        let (status, button) = post(&app, hx_post(&button)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(button.contains("Enable"));
+        assert_eq!(hx_post(&button), "/api/config/toggle-manifest-maintenance");
+    }
+
+    async fn post_preview(
+        app: &Router,
+        request: serde_json::Value,
+    ) -> (StatusCode, serde_json::Value) {
+        let response = app
+            .clone()
+            .oneshot(
+                Request::post("/api/filename-preview")
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(request.to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap_or_default())
+    }
+
+    #[tokio::test]
+    async fn filename_preview_sanitizes_sample_titles() {
+        let app = app();
+        let long_title = "Ünïcödé ".repeat(40);
+        let (status, preview) = post_preview(
+            &app,
+            serde_json::json!({
+                "samples": [
+                    {"title": "Café ☕ in 東京", "date": "20240315", "id": "abc"},
+                    {"title": long_title, "date": "20240316", "id": "def"},
+                    {"title": "What?! <Part 1/2>: \"A|B\"", "date": "20240317", "id": "ghi"},
+                ]
+            }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let filenames: Vec<&str> = preview["filenames"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|preview| preview["filename"].as_str().unwrap())
+            .collect();
+
+        assert_eq!(filenames[0], "20240315 - Café _ in 東京");
+        // Cut to fit the file name limit without splitting a character
+        assert!(filenames[1].starts_with("20240316 - Ünïcödé Ünïcödé"));
+        assert!(filenames[1].len() <= 200);
+        assert!(filenames[1].ends_with("Ünïcöd"));
+        assert_eq!(filenames[2], "20240317 - What_ _Part 1_2_ _A_B");
+
+        // Templates are checked before they're tried
+        let (status, _) = post_preview(
+            &app,
+            serde_json::json!({
+                "template": "{date} - {nope}",
+                "samples": [{"title": "A", "date": "20240315"}]
+            }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    async fn sse_body(failure: Option<String>) -> String {
+        let (tx, rx) = mpsc::channel(4);
+        let (result_tx, result_rx) = oneshot::channel();
+        tx.send("Processing A".to_string().into()).await.unwrap();
+        drop(tx);
+        result_tx.send(failure).unwrap();
+
+        let response = Sse::new(progress_events(rx, result_rx)).into_response();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn failed_sync_sends_an_error_event_before_completing() {
+        let body = sse_body(Some("Channel not found".to_string())).await;
+        let error = body.find("event: error\ndata: Channel not found").unwrap();
+        let complete = body.find("event: complete").unwrap();
+        assert!(body.find("Processing A").unwrap() < error);
+        assert!(error < complete);
+
+        let body = sse_body(None).await;
+        assert!(!body.contains("event: error"));
+        assert!(body.contains("event: complete\ndata: done"));
+    }
+
+    #[tokio::test]
+    async fn status_lists_stale_channels() {
+        let checked_hours_ago = |id: &str, hours: u64| Channel {
+            id: id.to_string(),
+            last_checked: SystemTime::now() - Duration::from_secs(hours * 60 * 60),
+            ..crate::config::test_channel(std::path::Path::new("/media/test"))
+        };
+        let app = app_with(Config {
+            check_interval: 60,
+            channels: vec![
+                checked_hours_ago("fresh", 1),
+                checked_hours_ago("overdue", 24),
+                // Never synced
+                crate::config::test_channel(std::path::Path::new("/media/new")),
+            ],
+            ..Config::default()
+        });
+
+        let response = app
+            .oneshot(Request::get("/api/status").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let status: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(status["stale_channels"], json!(["overdue"]));
+    }
+
+    #[tokio::test]
+    async fn cancel_route_cancels_only_that_channels_sync() {
+        let app = app();
+        let shutdown = CancellationToken::new();
+        let sync = crate::status::active_syncs().register("cancel-route-test", &shutdown);
+        let other = crate::status::active_syncs().register("cancel-route-other", &shutdown);
+
+        let (status, _) = post(&app, "/api/channels/cancel-route-test/cancel").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(sync.token.is_cancelled());
+        assert!(!other.token.is_cancelled());
+
+        drop(sync);
+        let (status, _) = post(&app, "/api/channels/cancel-route-test/cancel").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+}
@@ -1,27 +1,75 @@
 use axum::{
-    Form,
-    extract::{Path, State},
+    Form, Json,
+    extract::{Path, Query, State},
     http::StatusCode,
     response::{Html, IntoResponse, Response},
 };
 use minijinja::context;
 use serde::Deserialize;
+use serde_with::{NoneAsEmptyString, serde_as};
 use std::time::SystemTime;
 use tracing::error;
 
 use crate::AppStateArc;
-use crate::config::{Channel, Source};
+use crate::api::{
+    ResetQuery, channel_listing, check_geo_proxy, check_interval_override, non_empty, split_list,
+};
+use crate::config::{Channel, Source, VideoOrdering};
+use crate::library::{episode_index, remove_channel_files};
 
+#[serde_as]
 #[derive(Deserialize)]
 pub struct PlaylistForm {
     name: String,
     playlist_id: String,
+    #[serde_as(as = "NoneAsEmptyString")]
+    #[serde(default)]
+    max_videos: Option<usize>,
+    #[serde(default)]
+    ordering: VideoOrdering,
+    // Checkbox, only sent when ticked
+    download_subtitles: Option<String>,
+    #[serde(default)]
+    subtitle_langs: String,
+    #[serde_as(as = "NoneAsEmptyString")]
+    #[serde(default)]
+    season_offset: Option<i32>,
+    #[serde_as(as = "NoneAsEmptyString")]
+    #[serde(default)]
+    archive_after_years: Option<u32>,
+    #[serde_as(as = "NoneAsEmptyString")]
+    #[serde(default)]
+    check_interval_override: Option<u64>,
+    debug_logs: Option<String>,
+    #[serde(default)]
+    geo_proxy_url: String,
+    #[serde(default)]
+    tags: String,
+}
+
+/// Every playlist as JSON, the counterpart of `/api/channels`.
+pub async fn list_playlists(State(state): State<AppStateArc>) -> Response {
+    let config = state.config.read().await;
+    let playlists: Vec<_> = config
+        .channels
+        .iter()
+        .filter(|c| matches!(&c.source, Source::Playlist { .. }))
+        .map(channel_listing)
+        .collect();
+    Json(playlists).into_response()
 }
 
 pub async fn create_playlist(
     State(state): State<AppStateArc>,
     Form(form): Form<PlaylistForm>,
 ) -> Response {
+    if let Err(e) = check_geo_proxy(&form.geo_proxy_url) {
+        return (StatusCode::BAD_REQUEST, e).into_response();
+    }
+    let interval_override = match check_interval_override(form.check_interval_override) {
+        Ok(minutes) => minutes,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
     let mut config = state.config.write().await;
 
     // Check if playlist already exists
@@ -32,19 +80,35 @@ pub async fn create_playlist(
         return (StatusCode::BAD_REQUEST, "Playlist already exists").into_response();
     }
 
+    let previous = config.clone();
     let new_channel = Channel {
         id: form.playlist_id.clone(),
         source: Source::Playlist {
             id: form.playlist_id.clone(),
             name: form.name,
+            max_videos: form.max_videos,
+            ordering: form.ordering,
         },
         last_checked: SystemTime::UNIX_EPOCH,
         media_dir: config.jellyfin_media_path.join(&form.playlist_id),
+        download_subtitles: form.download_subtitles.is_some(),
+        subtitle_langs: split_list(&form.subtitle_langs),
+        prune_old: false,
+        prune_excess: false,
+        season_offset: form.season_offset.unwrap_or(0),
+        archive_after_years: form.archive_after_years.filter(|years| *years > 0),
+        check_interval_override: interval_override,
+        debug_logs: form.debug_logs.is_some(),
+        geo_proxy_url: non_empty(&form.geo_proxy_url),
+        media_dir_override: None,
+        paused: false,
+        last_result: None,
+        tags: split_list(&form.tags),
     };
 
     config.channels.push(new_channel);
 
-    if let Err(e) = config.save() {
+    if let Err(e) = config.save_or_restore(previous).await {
         error!("Failed to save config: {}", e);
         return (
             StatusCode::INTERNAL_SERVER_ERROR,
@@ -63,14 +127,38 @@ pub async fn update_playlist(
     Path(id): Path<String>,
     Form(form): Form<PlaylistForm>,
 ) -> Response {
+    if let Err(e) = check_geo_proxy(&form.geo_proxy_url) {
+        return (StatusCode::BAD_REQUEST, e).into_response();
+    }
+    let interval_override = match check_interval_override(form.check_interval_override) {
+        Ok(minutes) => minutes,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
     let mut config = state.config.write().await;
+    let previous = config.clone();
 
     if let Some(channel) = config.channels.iter_mut().find(|c| c.id == id) {
-        if let Source::Playlist { id, name } = &mut channel.source {
+        if let Source::Playlist {
+            id,
+            name,
+            max_videos,
+            ordering,
+        } = &mut channel.source
+        {
             *id = form.playlist_id;
             *name = form.name;
+            *max_videos = form.max_videos;
+            *ordering = form.ordering;
+            channel.download_subtitles = form.download_subtitles.is_some();
+            channel.subtitle_langs = split_list(&form.subtitle_langs);
+            channel.season_offset = form.season_offset.unwrap_or(0);
+            channel.archive_after_years = form.archive_after_years.filter(|years| *years > 0);
+            channel.check_interval_override = interval_override;
+            channel.debug_logs = form.debug_logs.is_some();
+            channel.geo_proxy_url = non_empty(&form.geo_proxy_url);
+            channel.tags = split_list(&form.tags);
 
-            if let Err(e) = config.save() {
+            if let Err(e) = config.save_or_restore(previous).await {
                 error!("Failed to save config: {}", e);
                 return (
                     StatusCode::INTERNAL_SERVER_ERROR,
@@ -90,13 +178,15 @@ pub async fn update_playlist(
 
 pub async fn delete_playlist(State(state): State<AppStateArc>, Path(id): Path<String>) -> Response {
     let mut config = state.config.write().await;
+    let previous = config.clone();
 
     // Only delete if it's a playlist
     config
         .channels
         .retain(|c| !matches!(&c.source, Source::Playlist { .. }) || c.id != id);
+    episode_index().forget_channel(&id);
 
-    if let Err(e) = config.save() {
+    if let Err(e) = config.save_or_restore(previous).await {
         error!("Failed to save config: {}", e);
         return (
             StatusCode::INTERNAL_SERVER_ERROR,
@@ -111,6 +201,7 @@ pub async fn delete_playlist(State(state): State<AppStateArc>, Path(id): Path<St
 pub async fn reset_playlist(
     State(state): State<AppStateArc>,
     Path(id): Path<String>,
+    Query(query): Query<ResetQuery>,
 ) -> impl IntoResponse {
     let mut config = state.config.write().await;
 
@@ -118,19 +209,30 @@ pub async fn reset_playlist(
         // Reset last_checked time
         channel.last_checked = SystemTime::UNIX_EPOCH;
 
-        // Delete media directory if it exists
-        if let Err(e) = tokio::fs::remove_dir_all(&channel.media_dir).await {
-            error!("Failed to delete directory: {}", e);
-            return (StatusCode::INTERNAL_SERVER_ERROR, "error occurred").into_response();
+        // Delete the episodes and artwork ytstrm wrote, nothing else in the directory
+        if !query.keep_files {
+            episode_index().forget_channel(&channel.id);
+            let media_dir = channel.media_dir.clone();
+            let removed = tokio::task::spawn_blocking(move || remove_channel_files(&media_dir))
+                .await
+                .map_err(std::io::Error::other)
+                .and_then(|result| result);
+            if let Err(e) = removed {
+                error!("Failed to delete channel files: {}", e);
+                return (StatusCode::INTERNAL_SERVER_ERROR, "error occurred").into_response();
+            }
         }
 
         // Save config
-        if let Err(e) = config.save() {
+        if let Err(e) = config.save().await {
             error!("Failed to save config: {}", e);
             return (StatusCode::INTERNAL_SERVER_ERROR, "error occurred").into_response();
         }
 
-        return Html(format!(r#"<span>Reset Playlist</span>"#)).into_response();
+        if query.keep_files {
+            return Html(r#"<span>Re-check Playlist</span>"#.to_string()).into_response();
+        }
+        return Html(r#"<span>Reset Playlist</span>"#.to_string()).into_response();
     }
 
     (StatusCode::NOT_FOUND, "Playlist not found").into_response()
@@ -1,13 +1,18 @@
-use axum::http::StatusCode;
+use axum::http::{HeaderMap, StatusCode, header};
 use axum::response::Html;
-use axum::{Form, extract::State, response::IntoResponse};
+use axum::{Form, Json, extract::State, response::IntoResponse};
 use minijinja::context;
-use serde::Deserialize;
-use std::path::PathBuf;
-use tracing::error;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tracing::{error, info};
 use url::Url;
 
-use crate::AppStateArc;
+use crate::config::{Channel, VideoInfo, redact_credentials};
+use crate::library::{episode_index, rewrite_strm_urls};
+use crate::migrations;
+use crate::schedule::HourWindow;
+use crate::status::active_syncs;
+use crate::{AppState, AppStateArc};
 
 #[derive(Deserialize)]
 pub struct ServerAddress {
@@ -19,9 +24,40 @@ pub struct CheckInterval {
     check_interval: u64,
 }
 
+#[derive(Deserialize)]
+pub struct SyncWindow {
+    sync_window: String,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct SleepIntervals {
+    sleep_interval_secs: u64,
+    max_sleep_interval_secs: u64,
+    per_video_delay_secs: u64,
+    // e.g. "0-7", empty to always use the values above
+    #[serde(default)]
+    quiet_hours: String,
+    quiet_sleep_interval_secs: u64,
+    quiet_max_sleep_interval_secs: u64,
+    quiet_per_video_delay_secs: u64,
+}
+
+#[derive(Deserialize)]
+pub struct ProxyUrl {
+    proxy_url: String,
+}
+
+#[derive(Deserialize)]
+pub struct CookiesPool {
+    // One cookie file path per line
+    cookies_pool: String,
+}
+
 #[derive(Deserialize)]
 pub struct MediaPath {
     jellyfin_media_path: String,
+    // Checkbox: also move existing channels' directories under the new path
+    rebase: Option<String>,
 }
 
 pub async fn update_server_address(
@@ -51,8 +87,9 @@ pub async fn update_server_address(
     }
 
     let mut config_guard = state.config.write().await;
+    let previous = config_guard.clone();
     config_guard.server_address = url_str.clone();
-    if let Err(e) = config_guard.save() {
+    if let Err(e) = config_guard.save_or_restore(previous).await {
         error!("Failed to save config: {}", e);
         return Html(
             state
@@ -84,13 +121,53 @@ pub async fn update_server_address(
     .into_response()
 }
 
+#[derive(Deserialize)]
+pub struct PublicBaseUrl {
+    // Empty goes back to server_address
+    public_base_url: String,
+}
+
+pub async fn update_public_base_url(
+    State(state): State<AppStateArc>,
+    Form(form): Form<PublicBaseUrl>,
+) -> impl IntoResponse {
+    let render = |value: &str, error: Option<String>| {
+        Html(
+            state
+                .templates
+                .render(
+                    "partials/settings/public_base_url_input.html",
+                    context! {
+                        value => value,
+                        error => error,
+                    },
+                )
+                .unwrap(),
+        )
+        .into_response()
+    };
+
+    let value = form.public_base_url.trim().to_string();
+    let mut config_guard = state.config.write().await;
+    let previous = config_guard.clone();
+    config_guard.public_base_url = (!value.is_empty()).then(|| value.clone());
+    // validate() rejects anything that isn't a plain http(s) URL
+    if let Err(e) = config_guard.save_or_restore(previous).await {
+        error!("Failed to save config: {}", e);
+        return render(&value, Some(e.to_string()));
+    }
+
+    render(&value, None)
+}
+
 pub async fn update_check_interval(
     State(state): State<AppStateArc>,
     Form(form): Form<CheckInterval>,
 ) -> impl IntoResponse {
     let mut config_guard = state.config.write().await;
+    let previous = config_guard.clone();
     config_guard.check_interval = form.check_interval;
-    if let Err(e) = config_guard.save() {
+    if let Err(e) = config_guard.save_or_restore(previous).await {
         error!("Failed to save config: {}", e);
         return Html(
             state
@@ -99,7 +176,7 @@ pub async fn update_check_interval(
                     "partials/settings/check_interval_input.html",
                     context! {
                         value => form.check_interval,
-                        error => "Failed to save configuration",
+                        error => e.to_string(),
                     },
                 )
                 .unwrap(),
@@ -122,21 +199,48 @@ pub async fn update_check_interval(
     .into_response()
 }
 
-pub async fn update_media_path(
+pub async fn update_sync_window(
     State(state): State<AppStateArc>,
-    Form(form): Form<MediaPath>,
+    Form(form): Form<SyncWindow>,
 ) -> impl IntoResponse {
-    let path = PathBuf::from(form.jellyfin_media_path.clone());
+    // An empty value clears the window so channels are checked at any hour
+    let window = if form.sync_window.trim().is_empty() {
+        None
+    } else {
+        match form.sync_window.parse::<HourWindow>() {
+            Ok(window) => Some(window),
+            Err(e) => {
+                return Html(
+                    state
+                        .templates
+                        .render(
+                            "partials/settings/sync_window_input.html",
+                            context! {
+                                value => form.sync_window,
+                                error => e.to_string(),
+                            },
+                        )
+                        .unwrap(),
+                )
+                .into_response();
+            }
+        }
+    };
+    let value = window.map(|w| w.to_string()).unwrap_or_default();
 
-    if !path.exists() {
+    let mut config_guard = state.config.write().await;
+    let previous = config_guard.clone();
+    config_guard.sync_window = window;
+    if let Err(e) = config_guard.save_or_restore(previous).await {
+        error!("Failed to save config: {}", e);
         return Html(
             state
                 .templates
                 .render(
-                    "partials/settings/media_path_input.html",
+                    "partials/settings/sync_window_input.html",
                     context! {
-                        value => form.jellyfin_media_path,
-                        error => "Directory does not exist",
+                        value => value,
+                        error => "Failed to save configuration",
                     },
                 )
                 .unwrap(),
@@ -144,90 +248,588 @@ pub async fn update_media_path(
         .into_response();
     }
 
+    Html(
+        state
+            .templates
+            .render(
+                "partials/settings/sync_window_input.html",
+                context! {
+                    value => value,
+                    error => None::<String>,
+                },
+            )
+            .unwrap(),
+    )
+    .into_response()
+}
+
+pub async fn update_sleep_intervals(
+    State(state): State<AppStateArc>,
+    Form(form): Form<SleepIntervals>,
+) -> impl IntoResponse {
+    let render = |error: Option<String>| {
+        Html(
+            state
+                .templates
+                .render(
+                    "partials/settings/sleep_intervals_input.html",
+                    context! {
+                        values => &form,
+                        quiet_hours => &form.quiet_hours,
+                        error => error,
+                    },
+                )
+                .unwrap(),
+        )
+        .into_response()
+    };
+
+    let mut config_guard = state.config.write().await;
+    // Validate a copy so a rejected value never reaches the live config
+    let mut updated = config_guard.clone();
+    updated.sleep_interval_secs = form.sleep_interval_secs;
+    updated.max_sleep_interval_secs = form.max_sleep_interval_secs;
+    updated.per_video_delay_secs = form.per_video_delay_secs;
+    updated.quiet_hours = if form.quiet_hours.trim().is_empty() {
+        None
+    } else {
+        match form.quiet_hours.parse::<HourWindow>() {
+            Ok(window) => Some(window),
+            Err(e) => return render(Some(format!("Quiet hours: {}", e))),
+        }
+    };
+    updated.quiet_sleep_interval_secs = form.quiet_sleep_interval_secs;
+    updated.quiet_max_sleep_interval_secs = form.quiet_max_sleep_interval_secs;
+    updated.quiet_per_video_delay_secs = form.quiet_per_video_delay_secs;
+    if let Err(e) = updated.validate() {
+        return render(Some(e.to_string()));
+    }
+
+    if let Err(e) = updated.save().await {
+        error!("Failed to save config: {}", e);
+        return render(Some("Failed to save configuration".to_string()));
+    }
+    *config_guard = updated;
+
+    render(None)
+}
+
+pub async fn update_proxy_url(
+    State(state): State<AppStateArc>,
+    Form(form): Form<ProxyUrl>,
+) -> impl IntoResponse {
+    let render = |value: &str, error: Option<&str>| {
+        Html(
+            state
+                .templates
+                .render(
+                    "partials/settings/proxy_url_input.html",
+                    context! {
+                        value => value,
+                        error => error,
+                    },
+                )
+                .unwrap(),
+        )
+        .into_response()
+    };
+
+    // An empty value clears the proxy
+    let proxy_url = form.proxy_url.trim();
+    let proxy_url = (!proxy_url.is_empty()).then(|| proxy_url.to_string());
+    if let Some(proxy) = &proxy_url
+        && Url::parse(proxy).is_err()
+    {
+        return render(&form.proxy_url, Some("Invalid proxy URL format"));
+    }
+
     let mut config_guard = state.config.write().await;
-    config_guard.jellyfin_media_path = path.clone();
-    if let Err(e) = config_guard.save() {
+    let previous = config_guard.clone();
+    config_guard.proxy_url = proxy_url.clone();
+    let value = proxy_url.unwrap_or_default();
+    if let Err(e) = config_guard.save_or_restore(previous).await {
         error!("Failed to save config: {}", e);
-        return Html(
+        return render(&value, Some("Failed to save configuration"));
+    }
+
+    render(&value, None)
+}
+
+pub async fn update_cookies_pool(
+    State(state): State<AppStateArc>,
+    Form(form): Form<CookiesPool>,
+) -> impl IntoResponse {
+    let render = |value: &str, error: Option<String>| {
+        Html(
+            state
+                .templates
+                .render(
+                    "partials/settings/cookies_pool_input.html",
+                    context! {
+                        value => value,
+                        error => error,
+                    },
+                )
+                .unwrap(),
+        )
+        .into_response()
+    };
+
+    let pool: Vec<PathBuf> = form
+        .cookies_pool
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(PathBuf::from)
+        .collect();
+    if let Some(missing) = pool.iter().find(|path| !path.is_file()) {
+        return render(
+            &form.cookies_pool,
+            Some(format!("Cookie file not found: {}", missing.display())),
+        );
+    }
+
+    let value = pool
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect::<Vec<_>>()
+        .join("\n");
+    let mut config_guard = state.config.write().await;
+    let previous = config_guard.clone();
+    config_guard.cookies_pool = pool;
+    if let Err(e) = config_guard.save_or_restore(previous).await {
+        error!("Failed to save config: {}", e);
+        return render(&value, Some("Failed to save configuration".to_string()));
+    }
+
+    render(&value, None)
+}
+
+pub async fn update_media_path(
+    State(state): State<AppStateArc>,
+    Form(form): Form<MediaPath>,
+) -> impl IntoResponse {
+    let path = PathBuf::from(form.jellyfin_media_path.clone());
+    let render = |value: &str, error: Option<&str>, changes: &[String]| {
+        Html(
             state
                 .templates
                 .render(
                     "partials/settings/media_path_input.html",
                     context! {
-                        value => path.display().to_string(),
-                        error => "Failed to save configuration",
+                        value => value,
+                        error => error,
+                        changes => changes,
                     },
                 )
                 .unwrap(),
         )
-        .into_response();
+        .into_response()
+    };
+
+    if !path.exists() {
+        return render(
+            &form.jellyfin_media_path,
+            Some("Directory does not exist"),
+            &[],
+        );
+    }
+
+    let mut config_guard = state.config.write().await;
+    let previous = config_guard.clone();
+    config_guard.jellyfin_media_path = path.clone();
+    let changes = if form.rebase.is_some() {
+        rebase_channels(&mut config_guard.channels, &path).await
+    } else {
+        // Channels keep the media_dir they were created with
+        let left_behind = config_guard
+            .channels
+            .iter()
+            .filter(|c| c.media_dir_override.is_none() && c.media_dir != c.resolve_media_dir(&path))
+            .count();
+        if left_behind > 0 {
+            vec![format!(
+                "{} channels still keep their episodes under the old path, tick \"Move existing channels\" to move them",
+                left_behind
+            )]
+        } else {
+            Vec::new()
+        }
+    };
+    // Moved directories stay moved, the config has to keep pointing at them
+    let saved = if form.rebase.is_some() {
+        config_guard.save().await
+    } else {
+        config_guard.save_or_restore(previous).await
+    };
+    if let Err(e) = saved {
+        error!("Failed to save config: {}", e);
+        return render(
+            &path.display().to_string(),
+            Some("Failed to save configuration"),
+            &changes,
+        );
+    }
+
+    render(&path.display().to_string(), None, &changes)
+}
+
+/// Moves the directory of every channel without a `media_dir_override` under `root`
+/// and points its `media_dir` there. Channels that are syncing, or whose directory
+/// can't be renamed (e.g. because `root` is on another disk), are left where they
+/// are. Returns a line per channel that was looked at.
+async fn rebase_channels(channels: &mut [Channel], root: &Path) -> Vec<String> {
+    let mut changes = Vec::new();
+    for channel in channels
+        .iter_mut()
+        .filter(|c| c.media_dir_override.is_none())
+    {
+        let target = channel.resolve_media_dir(root);
+        if channel.media_dir == target {
+            continue;
+        }
+        let name = channel.get_name().to_string();
+        if active_syncs().is_syncing(&channel.id) {
+            changes.push(format!("{}: skipped while it's syncing", name));
+            continue;
+        }
+
+        if tokio::fs::try_exists(&channel.media_dir)
+            .await
+            .unwrap_or(false)
+        {
+            if tokio::fs::try_exists(&target).await.unwrap_or(true) {
+                changes.push(format!("{}: {} already exists", name, target.display()));
+                continue;
+            }
+            if let Err(e) = tokio::fs::rename(&channel.media_dir, &target).await {
+                error!(
+                    "Failed to move {} to {}: {}",
+                    channel.media_dir.display(),
+                    target.display(),
+                    e
+                );
+                changes.push(format!(
+                    "{}: couldn't move {} ({}), move it by hand",
+                    name,
+                    channel.media_dir.display(),
+                    e
+                ));
+                continue;
+            }
+            changes.push(format!("{}: moved to {}", name, target.display()));
+        } else {
+            changes.push(format!("{}: will sync into {}", name, target.display()));
+        }
+
+        info!("Media directory of {} is now {}", name, target.display());
+        channel.media_dir = target;
+        episode_index().forget_channel(&channel.id);
+    }
+    changes
+}
+
+pub async fn toggle_background_tasks(State(state): State<AppStateArc>) -> impl IntoResponse {
+    let mut config = state.config.write().await;
+    let new_state = !config.background_tasks_paused;
+
+    if let Err(e) = config.set_background_tasks_paused(new_state).await {
+        return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
     }
 
     Html(
         state
             .templates
             .render(
-                "partials/settings/media_path_input.html",
-                context! {
-                    value => path.display().to_string(),
-                    error => None::<String>,
-                },
+                "partials/settings/background_tasks_toggle.html",
+                context! { paused => new_state },
             )
             .unwrap(),
     )
     .into_response()
 }
 
-pub async fn toggle_background_tasks(State(state): State<AppStateArc>) -> impl IntoResponse {
+pub async fn toggle_manifest_maintenance(State(state): State<AppStateArc>) -> impl IntoResponse {
     let mut config = state.config.write().await;
-    let new_state = !config.background_tasks_paused;
+    let new_state = !config.maintain_manifest_cache;
 
-    if let Err(e) = config.set_background_tasks_paused(new_state) {
+    if let Err(e) = config.set_maintain_manifest_cache(new_state).await {
         return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
     }
 
-    Html(format!(
-        r#"
-        <button
-            hx-post="/api/config/toggle-background-tasks"
-            hx-swap="outerHTML"
-            class="px-4 py-2 rounded-md font-medium {}">
-            {} Background Tasks
-        </button>
-    "#,
-        if new_state {
-            "bg-yellow-500 hover:bg-yellow-600 text-white"
-        } else {
-            "bg-green-500 hover:bg-green-600 text-white"
-        },
-        if new_state { "Resume" } else { "Pause" }
-    ))
+    Html(
+        state
+            .templates
+            .render(
+                "partials/settings/manifest_cache_toggle.html",
+                context! { enabled => new_state },
+            )
+            .unwrap(),
+    )
     .into_response()
 }
 
-pub async fn toggle_manifest_maintenance(State(state): State<AppStateArc>) -> impl IntoResponse {
+pub async fn toggle_dedup_across_channels(State(state): State<AppStateArc>) -> impl IntoResponse {
     let mut config = state.config.write().await;
-    let new_state = !config.maintain_manifest_cache;
+    let new_state = !config.dedup_across_channels;
 
-    if let Err(e) = config.set_maintain_manifest_cache(new_state) {
+    if let Err(e) = config.set_dedup_across_channels(new_state).await {
         return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
     }
 
-    Html(format!(
-        r#"
-        <button
-            hx-post="/api/config/toggle-manifest-maintenance"
-            hx-swap="outerHTML"
-            class="px-4 py-2 rounded-md font-medium {}">
-            {} Manifest Cache
-        </button>
-    "#,
-        if new_state {
-            "bg-green-500 hover:bg-green-600 text-white"
-        } else {
-            "bg-yellow-500 hover:bg-yellow-600 text-white"
-        },
-        if new_state { "Disable" } else { "Enable" }
-    ))
+    Html(
+        state
+            .templates
+            .render(
+                "partials/settings/dedup_toggle.html",
+                context! { enabled => new_state },
+            )
+            .unwrap(),
+    )
+    .into_response()
+}
+
+/// Rewrites existing .strm files to point at the current server address.
+pub async fn fix_strm_urls(State(state): State<AppStateArc>) -> impl IntoResponse {
+    let updated = rewrite_all_strm(&state).await;
+    Html(format!("<span>Updated {} .strm files</span>", updated))
+}
+
+#[derive(Serialize)]
+pub struct StrmRewriteSummary {
+    updated: usize,
+}
+
+/// `fix_strm_urls` for scripts, e.g. after moving the server: answers with the
+/// number of files rewritten as JSON.
+pub async fn rewrite_strm(State(state): State<AppStateArc>) -> Json<StrmRewriteSummary> {
+    let updated = rewrite_all_strm(&state).await;
+    Json(StrmRewriteSummary { updated })
+}
+
+/// Points every .strm at `public_base_url`, or `server_address` without one,
+/// keeping its video id.
+async fn rewrite_all_strm(state: &AppState) -> usize {
+    // The walk can take a while on a big library, so it runs off the lock
+    let (channels, stream_base) = {
+        let config = state.config.read().await;
+        (config.channels.clone(), config.stream_url(""))
+    };
+    let base = stream_base.clone();
+    let updated = tokio::task::spawn_blocking(move || {
+        rewrite_strm_urls(&channels, |video_id| format!("{}{}", base, video_id))
+    })
+    .await
+    .unwrap_or_else(|e| {
+        error!("Rewriting .strm files panicked: {}", e);
+        0
+    });
+    info!("Pointed {} .strm files at {}", updated, stream_base);
+    updated
+}
+
+#[derive(Deserialize)]
+pub struct FilenamePreviewRequest {
+    // Try a template before saving it; defaults to the configured one
+    template: Option<String>,
+    samples: Vec<FilenameSample>,
+}
+
+#[derive(Deserialize)]
+pub struct FilenameSample {
+    title: String,
+    // YYYYMMDD, as yt-dlp reports upload dates
+    date: String,
+    #[serde(default)]
+    id: String,
+    // Playlist position, rendered as {index} under playlist order
+    playlist_index: Option<u32>,
+    // Episode number within the season, for {index} and the SxxEyy prefix
+    episode: Option<u32>,
+}
+
+#[derive(Serialize)]
+pub struct FilenamePreview {
+    title: String,
+    filename: String,
+}
+
+#[derive(Serialize)]
+pub struct FilenamePreviewResponse {
+    template: String,
+    filenames: Vec<FilenamePreview>,
+}
+
+/// Shows the episode file names the current naming settings produce for some sample videos.
+pub async fn preview_filenames(
+    State(state): State<AppStateArc>,
+    Json(request): Json<FilenamePreviewRequest>,
+) -> impl IntoResponse {
+    let mut config = state.config.read().await.clone();
+    if let Some(template) = request.template {
+        config.filename_template = template;
+        if let Err(e) = config.validate() {
+            return (StatusCode::BAD_REQUEST, e.to_string()).into_response();
+        }
+    }
+
+    let filenames = request
+        .samples
+        .into_iter()
+        .map(|sample| {
+            let video = VideoInfo {
+                id: sample.id,
+                title: sample.title,
+                description: String::new(),
+                upload_date: sample.date,
+                thumbnail_url: String::new(),
+                maxres_thumbnail_url: None,
+                playlist_index: sample.playlist_index,
+                view_count: None,
+                duration: None,
+                live_status: None,
+                availability: None,
+                episode: sample.episode,
+                chapters: Vec::new(),
+            };
+            // The season a channel without a season offset would use
+            let season = video.upload_date.get(0..4).unwrap_or_default();
+            FilenamePreview {
+                filename: config.episode_name(
+                    &video,
+                    video.playlist_index,
+                    season.parse().unwrap_or(0),
+                ),
+                title: video.title,
+            }
+        })
+        .collect();
+
+    Json(FilenamePreviewResponse {
+        template: config.filename_template,
+        filenames,
+    })
     .into_response()
 }
+
+/// `current` if `imported` is its redacted form, as exported by this server.
+fn unredacted(imported: Option<String>, current: &Option<String>) -> Option<String> {
+    match current {
+        Some(current) if imported.as_deref() == Some(redact_credentials(current).as_str()) => {
+            Some(current.clone())
+        }
+        _ => imported,
+    }
+}
+
+// Export and import hand out or replace everything, so they need auth_token set
+const CONFIG_TRANSFER_NEEDS_AUTH: &str =
+    "Set auth_token in config.json to export or import the config";
+
+/// The config.json as a download, for backups and moving to another machine.
+/// Secrets (auth token, post-sync command, webhook URL, proxy passwords) are left
+/// out, see `Config::redacted`.
+pub async fn export_config(State(state): State<AppStateArc>) -> impl IntoResponse {
+    let config = state.config.read().await;
+    if config.auth_token.is_none() {
+        return (StatusCode::FORBIDDEN, CONFIG_TRANSFER_NEEDS_AUTH).into_response();
+    }
+    match serde_json::to_string_pretty(&config.redacted()) {
+        Ok(json) => (
+            [
+                (header::CONTENT_TYPE, "application/json".to_string()),
+                (
+                    header::CONTENT_DISPOSITION,
+                    format!(
+                        "attachment; filename=\"ytstrm-config-{}.json\"",
+                        chrono::Local::now().format("%Y%m%d")
+                    ),
+                ),
+            ],
+            json,
+        )
+            .into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+/// Replaces the config with an exported one, sent as application/json so a
+/// cross-site form can't post it. Older config formats are migrated the way they
+/// are at startup; anything that doesn't parse or validate is refused with a 400
+/// and the current config is left alone. The auth token, post-sync command and
+/// media directory overrides can only be set in config.json itself, so they are
+/// kept from the current config rather than imported.
+pub async fn import_config(
+    State(state): State<AppStateArc>,
+    headers: HeaderMap,
+    body: String,
+) -> impl IntoResponse {
+    let is_json = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.trim_start().starts_with("application/json"));
+    if !is_json {
+        return (
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "The config must be sent as application/json",
+        )
+            .into_response();
+    }
+    if state.config.read().await.auth_token.is_none() {
+        return (StatusCode::FORBIDDEN, CONFIG_TRANSFER_NEEDS_AUTH).into_response();
+    }
+
+    let mut imported = match migrations::parse_config(&body) {
+        Ok(imported) => imported,
+        Err(e) => {
+            return (StatusCode::BAD_REQUEST, format!("Invalid config: {}", e)).into_response();
+        }
+    };
+    if let Err(e) = imported.validate() {
+        return (StatusCode::BAD_REQUEST, format!("Invalid config: {}", e)).into_response();
+    }
+    if !imported.jellyfin_media_path.is_dir() {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!(
+                "Media path {} does not exist on this machine",
+                imported.jellyfin_media_path.display()
+            ),
+        )
+            .into_response();
+    }
+
+    let mut config = state.config.write().await;
+    imported.auth_token = config.auth_token.clone();
+    imported.post_sync_command = config.post_sync_command.clone();
+    // An export of this server comes back with its secrets redacted, keep the real ones
+    if imported.notify_webhook_url.is_none() {
+        imported.notify_webhook_url = config.notify_webhook_url.clone();
+    }
+    imported.proxy_url = unredacted(imported.proxy_url, &config.proxy_url);
+    for channel in &mut imported.channels {
+        let current = config
+            .channels
+            .iter()
+            .find(|current| current.id == channel.id);
+        channel.media_dir_override = current.and_then(|current| current.media_dir_override.clone());
+        channel.media_dir = channel.resolve_media_dir(&imported.jellyfin_media_path);
+        if let Some(current) = current {
+            channel.geo_proxy_url =
+                unredacted(channel.geo_proxy_url.take(), &current.geo_proxy_url);
+        }
+    }
+    if let Err(e) = imported.save().await {
+        error!("Failed to save imported config: {}", e);
+        return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
+    }
+    // Channels may now live in other directories, reload their episodes on next use
+    for channel in config.channels.iter().chain(&imported.channels) {
+        episode_index().forget_channel(&channel.id);
+    }
+    info!("Imported config with {} channels", imported.channels.len());
+    let message = format!("Imported {} channels", imported.channels.len());
+    *config = imported;
+
+    (StatusCode::OK, message).into_response()
+}
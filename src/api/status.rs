@@ -0,0 +1,59 @@
+use axum::{Json, extract::State, response::Html};
+use chrono::{DateTime, Local};
+use minijinja::context;
+use serde::Serialize;
+use std::time::{Duration, SystemTime};
+
+use crate::AppStateArc;
+use crate::status::{SweepStatus, sweep_tracker};
+
+#[derive(Serialize)]
+pub struct StatusResponse {
+    background_tasks_paused: bool,
+    // Ids of channels not checked for several intervals, see Channel::is_stale
+    stale_channels: Vec<String>,
+    #[serde(flatten)]
+    sweep: SweepStatus,
+}
+
+pub async fn status(State(state): State<AppStateArc>) -> Json<StatusResponse> {
+    let config = state.config.read().await;
+    let stale_channels = config
+        .channels
+        .iter()
+        .filter(|channel| channel.is_stale(&config))
+        .map(|channel| channel.id.clone())
+        .collect();
+    Json(StatusResponse {
+        background_tasks_paused: config.background_tasks_paused,
+        stale_channels,
+        sweep: sweep_tracker().snapshot(),
+    })
+}
+
+/// The status line polled by the settings page.
+pub async fn status_view(State(state): State<AppStateArc>) -> Html<String> {
+    let background_tasks_paused = state.config.read().await.background_tasks_paused;
+    let sweep = sweep_tracker().snapshot();
+    Html(
+        state
+            .templates
+            .render(
+                "partials/sweep_status.html",
+                context! {
+                    paused => background_tasks_paused,
+                    current_channel => sweep.current_channel,
+                    current_position => sweep.current_position,
+                    sweep_size => sweep.sweep_size,
+                    last_sweep_finished => sweep.last_sweep_finished.map(format_time),
+                    next_sweep => sweep.next_sweep.map(format_time),
+                },
+            )
+            .unwrap(),
+    )
+}
+
+fn format_time(unix_secs: u64) -> String {
+    let time = DateTime::<Local>::from(SystemTime::UNIX_EPOCH + Duration::from_secs(unix_secs));
+    time.format("%Y-%m-%d %H:%M").to_string()
+}
@@ -0,0 +1,85 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use tracing::{error, info};
+
+use crate::AppStateArc;
+use crate::library::{self, episode_index};
+use crate::manifest::clear_cached;
+
+#[derive(Deserialize)]
+pub struct ReprocessQuery {
+    channel: String,
+}
+
+pub async fn reprocess_video(
+    State(state): State<AppStateArc>,
+    Path(video_id): Path<String>,
+    Query(query): Query<ReprocessQuery>,
+) -> Response {
+    // Snapshot so the lock isn't held while yt-dlp runs
+    let (channel, settings) = {
+        let config = state.config.read().await;
+        let Some(channel) = config.channels.iter().find(|c| c.id == query.channel) else {
+            return (StatusCode::NOT_FOUND, "Channel not found").into_response();
+        };
+        (channel.clone(), config.clone())
+    };
+
+    info!("Reprocessing video {} for {}", video_id, channel.get_name());
+    match channel.reprocess_video(&video_id, &settings, &None).await {
+        Ok(video) => (StatusCode::OK, format!("Reprocessed {}", video.title)).into_response(),
+        Err(e) => {
+            error!("Failed to reprocess video {}: {}", video_id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct DeleteEpisodeQuery {
+    // Also drop the cached manifest instead of waiting for it to be evicted
+    #[serde(default)]
+    manifest: bool,
+}
+
+/// Deletes one episode of a channel or playlist. It comes back on a later sync only
+/// if a scan still covers its upload date, e.g. after a reset.
+pub async fn delete_episode(
+    State(state): State<AppStateArc>,
+    Path((id, video_id)): Path<(String, String)>,
+    Query(query): Query<DeleteEpisodeQuery>,
+) -> Response {
+    let (media_dir, cache_dir) = {
+        let config = state.config.read().await;
+        let Some(channel) = config.channels.iter().find(|c| c.id == id) else {
+            return (StatusCode::NOT_FOUND, "Channel not found").into_response();
+        };
+        (
+            channel.media_dir.clone(),
+            config.jellyfin_media_path.join("manifests"),
+        )
+    };
+
+    match library::delete_episode(&media_dir, &video_id) {
+        Ok(true) => {}
+        Ok(false) => return (StatusCode::NOT_FOUND, "Episode not found").into_response(),
+        Err(e) => {
+            error!("Failed to delete episode {}: {}", video_id, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
+        }
+    }
+    episode_index().remove(&id, std::slice::from_ref(&video_id));
+
+    if query.manifest
+        && let Err(e) = clear_cached(&cache_dir, Some(&video_id))
+    {
+        error!("Failed to delete cached manifest of {}: {}", video_id, e);
+    }
+
+    info!("Deleted episode {} of {}", video_id, id);
+    (StatusCode::OK, format!("Deleted {}", video_id)).into_response()
+}
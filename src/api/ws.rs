@@ -0,0 +1,109 @@
+use axum::{
+    extract::{
+        Path, State,
+        ws::{Message, WebSocket, WebSocketUpgrade},
+    },
+    response::Response,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tracing::{error, info};
+
+use crate::AppStateArc;
+
+/// Sent to the client as JSON text frames, after the sync's own `SyncProgress`
+/// messages.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum SyncEvent {
+    // The sync failed, unlike a `SyncProgress` error about a single video
+    Error { message: String },
+    // Always the last event, after an error if the sync failed
+    Complete,
+}
+
+/// Accepted from the client as JSON text frames, e.g. `{"type":"cancel"}`.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ControlMessage {
+    // Stop the sync once the video being written is done
+    Cancel,
+}
+
+/// Syncs a channel like `/api/progress/{id}`, over a WebSocket so the client can
+/// also cancel it.
+pub async fn progress_ws_handler(
+    State(state): State<AppStateArc>,
+    Path(id): Path<String>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    ws.on_upgrade(move |socket| sync_over_socket(state, id, socket))
+}
+
+async fn sync_over_socket(state: AppStateArc, id: String, mut socket: WebSocket) {
+    let channel = {
+        let config = state.config.read().await;
+        config.channels.iter().find(|c| c.id == id).cloned()
+    };
+    let Some(channel) = channel else {
+        let message = format!("Channel {} not found", id);
+        let _ = send_event(&mut socket, &SyncEvent::Error { message }).await;
+        let _ = send_event(&mut socket, &SyncEvent::Complete).await;
+        return;
+    };
+
+    info!("Processing videos for channel {} over WebSocket", id);
+    let (tx, mut rx) = mpsc::channel(100);
+    // Also cancelled on shutdown, like every other sync
+    let cancel = state.shutdown.child_token();
+    let sync = {
+        let state_clone = state.clone();
+        let cancel = cancel.clone();
+        state.tasks.spawn(async move {
+            channel
+                .process_new_videos(&state_clone.config, Some(tx), &cancel, None)
+                .await
+        })
+    };
+
+    loop {
+        tokio::select! {
+            progress = rx.recv() => match progress {
+                Some(progress) => {
+                    if send_event(&mut socket, &progress).await.is_err() {
+                        // The client left, the sync carries on without it
+                        return;
+                    }
+                }
+                None => break,
+            },
+            incoming = socket.recv() => match incoming {
+                Some(Ok(Message::Text(text))) => {
+                    if let Ok(ControlMessage::Cancel) = serde_json::from_str(&text) {
+                        info!("Cancelling sync of {} after the current video", id);
+                        cancel.cancel();
+                    }
+                }
+                Some(Ok(_)) => {}
+                None | Some(Err(_)) => return,
+            },
+        }
+    }
+
+    // The progress channel closes when the sync ends
+    let failure = match sync.await {
+        Ok(Ok(_)) => None,
+        Ok(Err(e)) => Some(format!("{:#}", e)),
+        Err(e) => Some(e.to_string()),
+    };
+    if let Some(message) = failure {
+        error!("Error processing videos: {}", message);
+        let _ = send_event(&mut socket, &SyncEvent::Error { message }).await;
+    }
+    let _ = send_event(&mut socket, &SyncEvent::Complete).await;
+}
+
+async fn send_event(socket: &mut WebSocket, event: &impl Serialize) -> Result<(), axum::Error> {
+    let text = serde_json::to_string(event).expect("sync events serialize");
+    socket.send(Message::Text(text.into())).await
+}
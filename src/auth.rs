@@ -0,0 +1,124 @@
+use axum::{
+    Form,
+    extract::{Request, State},
+    http::{HeaderMap, StatusCode, header},
+    middleware::Next,
+    response::{Html, IntoResponse, Redirect, Response},
+};
+use minijinja::context;
+use percent_encoding::{NON_ALPHANUMERIC, percent_decode_str, utf8_percent_encode};
+use serde::Deserialize;
+use tracing::info;
+
+use crate::AppStateArc;
+
+const COOKIE_NAME: &str = "ytstrm_token";
+
+#[derive(Deserialize)]
+pub struct LoginForm {
+    token: String,
+}
+
+/// Rejects requests without the configured token, either as an
+/// `Authorization: Bearer` header or the cookie set by the login page.
+/// Does nothing while `auth_token` is unset.
+pub async fn require_auth(
+    State(state): State<AppStateArc>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(expected) = state.config.read().await.auth_token.clone() else {
+        return next.run(request).await;
+    };
+
+    let headers = request.headers();
+    if bearer_token(headers).is_some_and(|token| tokens_match(&token, &expected))
+        || cookie_token(headers).is_some_and(|token| tokens_match(&token, &expected))
+    {
+        return next.run(request).await;
+    }
+
+    // Send people using the UI to the login page, API clients just get the status
+    let from_browser = headers
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/html"));
+    if from_browser && !headers.contains_key("HX-Request") {
+        Redirect::to("/login").into_response()
+    } else {
+        (StatusCode::UNAUTHORIZED, "Authentication required").into_response()
+    }
+}
+
+pub async fn login_page(State(state): State<AppStateArc>) -> Html<String> {
+    Html(
+        state
+            .templates
+            .render("login.html", context! { error => None::<&str> })
+            .unwrap(),
+    )
+}
+
+pub async fn login(State(state): State<AppStateArc>, Form(form): Form<LoginForm>) -> Response {
+    let (expected, secure) = {
+        let config = state.config.read().await;
+        // Served over HTTPS, directly or behind a proxy
+        let secure = config.tls_paths().is_some()
+            || config
+                .public_base_url
+                .as_deref()
+                .is_some_and(|url| url.starts_with("https://"));
+        (config.auth_token.clone(), secure)
+    };
+    if expected.is_some_and(|expected| !tokens_match(&form.token, &expected)) {
+        info!("Rejected login with an invalid token");
+        let html = state
+            .templates
+            .render("login.html", context! { error => "Invalid token" })
+            .unwrap();
+        return (StatusCode::UNAUTHORIZED, Html(html)).into_response();
+    }
+
+    let mut cookie = format!(
+        "{}={}; Path=/; HttpOnly; SameSite=Strict",
+        COOKIE_NAME,
+        utf8_percent_encode(&form.token, NON_ALPHANUMERIC)
+    );
+    // Never sent over plain HTTP then
+    if secure {
+        cookie.push_str("; Secure");
+    }
+    ([(header::SET_COOKIE, cookie)], Redirect::to("/")).into_response()
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(|token| token.trim().to_string())
+}
+
+fn cookie_token(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .find_map(|pair| {
+            let (name, value) = pair.trim().split_once('=')?;
+            (name == COOKIE_NAME)
+                .then(|| percent_decode_str(value).decode_utf8_lossy().into_owned())
+        })
+}
+
+// Compares every byte so response timing doesn't reveal how much of a guess was right
+fn tokens_match(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
@@ -0,0 +1,183 @@
+// Most filesystems cap names at 255 bytes; this leaves room for suffixes like
+// "-thumb.jpg", ".ytstrm.json", subtitle languages and the collision hash
+const MAX_BASENAME_BYTES: usize = 200;
+
+// Device names Windows refuses as file names, with or without an extension
+const WINDOWS_RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Keeps letters and digits (accented ones included), spaces and dashes, replacing
+/// everything else with a single `_`. Runs of spaces collapse too, and neither is
+/// left at the ends.
+pub fn create_safe_filename(base: &str, windows_safe: bool) -> String {
+    let mut safe = String::with_capacity(base.len());
+    for c in base.chars() {
+        let c = if c.is_alphanumeric() || c == '-' || c == ' ' {
+            c
+        } else {
+            '_'
+        };
+        if (c == '_' || c == ' ') && safe.ends_with(c) {
+            continue;
+        }
+        safe.push(c);
+    }
+    let mut safe = truncate_bytes(safe.trim_matches(['_', ' ']), MAX_BASENAME_BYTES)
+        .trim_end_matches(['_', ' '])
+        .to_string();
+    if safe.is_empty() {
+        safe.push_str("untitled");
+    }
+
+    if windows_safe {
+        make_windows_safe(safe)
+    } else {
+        safe
+    }
+}
+
+/// The longest prefix of `s` that fits in `max` bytes without splitting a character.
+fn truncate_bytes(s: &str, max: usize) -> &str {
+    let mut end = s.len().min(max);
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+fn make_windows_safe(name: String) -> String {
+    // Windows strips trailing dots and spaces, which would break the .strm/.nfo pairing
+    let mut name = name.trim_end_matches(['.', ' ']).to_string();
+    if name.is_empty() {
+        name.push('_');
+    }
+
+    let stem = name.split('.').next().unwrap_or_default().trim_end();
+    if WINDOWS_RESERVED_NAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(stem))
+    {
+        name.insert(stem.len(), '_');
+    }
+
+    name
+}
+
+/// Short, stable tag for a video id, appended to tell apart episodes whose titles
+/// sanitize to the same name. FNV-1a, so it is the same on every build.
+pub fn short_id_hash(video_id: &str) -> String {
+    let hash = video_id.bytes().fold(0x811c9dc5u32, |hash, byte| {
+        (hash ^ byte as u32).wrapping_mul(0x01000193)
+    });
+    format!("{:08x}", hash)
+}
+
+// Placeholders filename_template may use
+pub const EPISODE_TEMPLATE_FIELDS: [&str; 4] = ["date", "title", "id", "index"];
+
+/// Substitutes `{field}` placeholders in an episode filename template. Placeholders
+/// without a value are left as they are.
+pub fn render_filename_template(template: &str, values: &[(&str, &str)]) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let value = after.find('}').and_then(|end| {
+            values
+                .iter()
+                .find(|(name, _)| *name == &after[..end])
+                .map(|(_, value)| (*value, end))
+        });
+        match value {
+            Some((value, end)) => {
+                rendered.push_str(value);
+                rest = &after[end + 1..];
+            }
+            None => {
+                rendered.push('{');
+                rest = after;
+            }
+        }
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+/// Placeholder names used in `template`, in order.
+pub fn template_placeholders(template: &str) -> Vec<&str> {
+    template
+        .split('{')
+        .skip(1)
+        .filter_map(|part| part.split_once('}').map(|(name, _)| name))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn titles_that_are_windows_device_names_get_renamed() {
+        for (title, safe) in [
+            ("CON", "CON_"),
+            ("aux", "aux_"),
+            ("Com1.", "Com1_"),
+            ("  LPT9  ", "LPT9_"),
+            ("nul?", "nul_"),
+        ] {
+            assert_eq!(create_safe_filename(title, true), safe, "{}", title);
+        }
+
+        // Only the whole name is reserved, not names that start with one
+        assert_eq!(create_safe_filename("CON - Live", true), "CON - Live");
+        assert_eq!(create_safe_filename("COM10", true), "COM10");
+        // Left alone where Windows doesn't matter
+        assert_eq!(create_safe_filename("CON", false), "CON");
+    }
+
+    #[test]
+    fn reserved_stems_and_trailing_dots_are_fixed_before_the_extension() {
+        assert_eq!(make_windows_safe("nul.txt".to_string()), "nul_.txt");
+        assert_eq!(make_windows_safe("Title. . ".to_string()), "Title");
+        assert_eq!(make_windows_safe("...".to_string()), "_");
+    }
+
+    #[test]
+    fn unicode_titles_keep_their_letters() {
+        assert_eq!(create_safe_filename("Café au lait", false), "Café au lait");
+        assert_eq!(create_safe_filename("東京 vlog #3", false), "東京 vlog _3");
+        assert_eq!(
+            create_safe_filename("Ελληνικά - Part 2", false),
+            "Ελληνικά - Part 2"
+        );
+    }
+
+    #[test]
+    fn emoji_and_punctuation_collapse_to_one_underscore() {
+        assert_eq!(create_safe_filename("Café ☕", false), "Café");
+        assert_eq!(
+            create_safe_filename("🔥🔥 Big News!!! 🔥🔥", false),
+            "Big News"
+        );
+        assert_eq!(
+            create_safe_filename("What?! Really...", false),
+            "What_ Really"
+        );
+        assert_eq!(create_safe_filename("a   b", false), "a b");
+        assert_eq!(create_safe_filename("☕🍵", false), "untitled");
+    }
+
+    #[test]
+    fn titles_that_sanitize_alike_are_told_apart_by_id() {
+        assert_eq!(
+            create_safe_filename("Café ☕", false),
+            create_safe_filename("Café 🍵", false)
+        );
+        assert_ne!(short_id_hash("abc123"), short_id_hash("abc124"));
+        assert_eq!(short_id_hash("abc123"), short_id_hash("abc123"));
+        assert_eq!(short_id_hash("abc123").len(), 8);
+    }
+}
@@ -0,0 +1,139 @@
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::process::Command;
+use tracing::{error, info};
+
+use crate::config::Channel;
+
+// Long enough for a backup or a library scan trigger, short enough not to stall syncing
+const POST_SYNC_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Runs the user's `post_sync_command` once `channel` has synced. The command is
+/// split into a program and arguments like a shell would, but run without one;
+/// the channel id and new video count are appended as arguments and passed in
+/// `YTSTRM_*` environment variables. Failures are logged, never returned, so a
+/// broken hook can't fail the sync.
+pub async fn run_post_sync_command(command: &str, channel: &Channel, new_videos: usize) {
+    let Some((program, args)) = split_command(command) else {
+        error!(
+            "Post-sync command for {} is empty or has unbalanced quotes",
+            channel.id
+        );
+        return;
+    };
+    let mut hook = Command::new(program);
+    hook.args(args)
+        .arg(&channel.id)
+        .arg(new_videos.to_string())
+        .env("YTSTRM_CHANNEL_ID", &channel.id)
+        .env("YTSTRM_CHANNEL_NAME", channel.get_name())
+        .env("YTSTRM_MEDIA_DIR", &channel.media_dir)
+        .env("YTSTRM_NEW_VIDEOS", new_videos.to_string())
+        .stdin(Stdio::null())
+        .kill_on_drop(true);
+
+    let output = match tokio::time::timeout(POST_SYNC_TIMEOUT, hook.output()).await {
+        Ok(Ok(output)) => output,
+        Ok(Err(e)) => {
+            error!("Failed to run post-sync command for {}: {}", channel.id, e);
+            return;
+        }
+        Err(_) => {
+            error!(
+                "Post-sync command for {} timed out after {}s",
+                channel.id,
+                POST_SYNC_TIMEOUT.as_secs()
+            );
+            return;
+        }
+    };
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !stdout.trim().is_empty() {
+        info!("Post-sync command output: {}", stdout.trim());
+    }
+    if output.status.success() {
+        info!("Post-sync command for {} finished", channel.id);
+    } else {
+        error!(
+            "Post-sync command for {} exited with {}: {}",
+            channel.id,
+            output.status,
+            stderr.trim()
+        );
+    }
+}
+
+/// The program and arguments of a post-sync command, None if it has none or its
+/// quotes don't balance.
+pub fn split_command(command: &str) -> Option<(String, Vec<String>)> {
+    let mut words = shlex::split(command)?;
+    if words.is_empty() {
+        return None;
+    }
+    let program = words.remove(0);
+    Some((program, words))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_channel;
+
+    #[test]
+    fn commands_split_like_a_shell_would() {
+        assert_eq!(
+            split_command(r#"/usr/bin/notify --title "New videos" it's"#),
+            None
+        );
+        assert_eq!(
+            split_command(r#"/usr/bin/notify --title "New videos" 'a b'"#),
+            Some((
+                "/usr/bin/notify".to_string(),
+                vec![
+                    "--title".to_string(),
+                    "New videos".to_string(),
+                    "a b".to_string()
+                ]
+            ))
+        );
+        assert_eq!(split_command("   "), None);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn hook_gets_the_channel_and_count() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("out");
+        let script = format!(
+            r#"printf '%s|%s|%s|%s|%s' "$YTSTRM_CHANNEL_ID" "$YTSTRM_NEW_VIDEOS" "$YTSTRM_MEDIA_DIR" "$1" "$2" > {}"#,
+            shlex::try_quote(out.to_str().unwrap()).unwrap()
+        );
+        // Without a shell, metacharacters only reach one if the command asks for it
+        let command = format!("sh -c {} hook", shlex::try_quote(&script).unwrap());
+        let channel = test_channel(dir.path());
+
+        run_post_sync_command(&command, &channel, 3).await;
+
+        assert_eq!(
+            std::fs::read_to_string(&out).unwrap(),
+            format!("{}|3|{}|{}|3", channel.id, dir.path().display(), channel.id)
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn hook_arguments_are_not_interpreted_by_a_shell() {
+        let dir = tempfile::tempdir().unwrap();
+        let marker = dir.path().join("marker");
+        let command = format!(
+            "echo ; touch {}",
+            shlex::try_quote(marker.to_str().unwrap()).unwrap()
+        );
+
+        run_post_sync_command(&command, &test_channel(dir.path()), 0).await;
+
+        assert!(!marker.exists());
+    }
+}
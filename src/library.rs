@@ -0,0 +1,462 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex};
+
+use tracing::error;
+
+use crate::config::{Channel, VideoInfo, write_atomic};
+
+/// The video id a .strm file streams, from its `http(s)://host/stream/<id>` URL.
+pub fn strm_video_id(content: &str) -> Option<&str> {
+    content
+        .trim()
+        .rsplit_once("/stream/")
+        .map(|(_, id)| id)
+        .filter(|id| !id.is_empty())
+}
+
+/// Points every .strm of `channels` at `stream_url` of its video id, e.g. after the
+/// server address or public base URL changed. Files already pointing there are left
+/// alone, so running it twice is harmless. Returns how many files were rewritten.
+pub fn rewrite_strm_urls(channels: &[Channel], stream_url: impl Fn(&str) -> String) -> usize {
+    let mut updated = 0;
+    for path in channels.iter().flat_map(|c| strm_files(&c.media_dir)) {
+        let Ok(content) = fs::read_to_string(&path) else {
+            continue;
+        };
+        let content = content.trim();
+        let Some(video_id) = strm_video_id(content) else {
+            continue;
+        };
+
+        let rewritten = stream_url(video_id);
+        if rewritten == content {
+            continue;
+        }
+        match write_atomic(&path, &rewritten) {
+            Ok(()) => updated += 1,
+            Err(e) => error!("Failed to rewrite {}: {}", path.display(), e),
+        }
+    }
+    updated
+}
+
+/// Every .strm under a channel's `Season N` directories.
+pub fn strm_files(media_dir: &Path) -> Vec<PathBuf> {
+    let Ok(seasons) = fs::read_dir(media_dir) else {
+        return Vec::new();
+    };
+
+    seasons
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .filter_map(|season_dir| fs::read_dir(season_dir).ok())
+        .flat_map(|entries| entries.flatten().map(|entry| entry.path()))
+        .filter(|path| path.extension().and_then(|e| e.to_str()) == Some("strm"))
+        .collect()
+}
+
+/// Metadata of the episodes in a season directory, from their sidecars. Episodes
+/// written before sidecars existed are left out.
+pub fn season_episodes(season_dir: &Path) -> Vec<VideoInfo> {
+    let Ok(entries) = fs::read_dir(season_dir) else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().and_then(|e| e.to_str()) == Some("strm"))
+        .filter_map(|path| VideoInfo::load_sidecar(&path).ok())
+        .collect()
+}
+
+/// Base name (no extension) and sidecar of the episode in `season_dir` whose .strm
+/// streams `video_id`. Episodes without a sidecar aren't found.
+pub fn find_episode(season_dir: &Path, video_id: &str) -> Option<(String, VideoInfo)> {
+    fs::read_dir(season_dir)
+        .ok()?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().and_then(|e| e.to_str()) == Some("strm"))
+        .filter(|path| {
+            fs::read_to_string(path).is_ok_and(|content| strm_video_id(&content) == Some(video_id))
+        })
+        .find_map(|path| {
+            let video = VideoInfo::load_sidecar(&path).ok()?;
+            let base = path.file_stem()?.to_str()?.to_string();
+            Some((base, video))
+        })
+}
+
+/// The highest episode number used in a season directory, 0 if there is none.
+pub fn highest_episode_number(season_dir: &Path) -> u32 {
+    season_episodes(season_dir)
+        .iter()
+        .filter_map(|video| video.episode)
+        .max()
+        .unwrap_or(0)
+}
+
+/// Ids of all videos that already have a .strm in any of `channels`.
+pub fn materialized_video_ids(channels: &[Channel]) -> HashSet<String> {
+    channels
+        .iter()
+        .flat_map(|channel| strm_files(&channel.media_dir))
+        .filter_map(|path| fs::read_to_string(path).ok())
+        .filter_map(|content| strm_video_id(&content).map(str::to_string))
+        .collect()
+}
+
+/// Upload date of an episode file ytstrm wrote, from its `YYYYMMDD - title` name.
+/// Anything not matching that pattern returns None and is left alone.
+pub fn episode_file_date(file_name: &str) -> Option<&str> {
+    let date = file_name.get(0..8)?;
+    let rest = &file_name[8..];
+    (date.bytes().all(|b| b.is_ascii_digit()) && rest.starts_with(" - ")).then_some(date)
+}
+
+/// Upload dates (YYYYMMDD) of the episodes in `media_dir`, one per .strm, newest
+/// first. Episodes without a date in their name are dated from their sidecar, and
+/// left out if they have none.
+pub fn episode_upload_dates(media_dir: &Path) -> Vec<String> {
+    let mut dates: Vec<String> = strm_files(media_dir)
+        .iter()
+        .filter_map(|path| {
+            let stem = path.file_stem()?.to_str()?;
+            match episode_file_date(stem) {
+                Some(date) => Some(date.to_string()),
+                None => Some(VideoInfo::load_sidecar(path).ok()?.upload_date),
+            }
+        })
+        .collect();
+    dates.sort_unstable_by(|a, b| b.cmp(a));
+    dates
+}
+
+/// Deletes episode files uploaded before `cutoff` (YYYYMMDD) and any season
+/// directories left empty. Returns the ids of videos whose .strm was removed.
+/// Episodes named by a filename template without a leading date are dated from
+/// their .ytstrm.json sidecar.
+pub fn prune_episodes_before(media_dir: &Path, cutoff: &str) -> Vec<String> {
+    let mut pruned_ids = Vec::new();
+    let Ok(seasons) = fs::read_dir(media_dir) else {
+        return pruned_ids;
+    };
+
+    for season_dir in seasons.flatten().map(|entry| entry.path()) {
+        let is_season = season_dir
+            .file_name()
+            .and_then(|n| n.to_str())
+            .is_some_and(|n| n.starts_with("Season "));
+        if !is_season || !season_dir.is_dir() {
+            continue;
+        }
+
+        let Ok(entries) = fs::read_dir(&season_dir) else {
+            continue;
+        };
+        let paths: Vec<PathBuf> = entries.flatten().map(|entry| entry.path()).collect();
+
+        // Base names of old episodes that don't carry their date in the name
+        let undated_old: Vec<String> = paths
+            .iter()
+            .filter(|path| path.extension().and_then(|e| e.to_str()) == Some("strm"))
+            .filter_map(|path| {
+                let stem = path.file_stem()?.to_str()?;
+                if episode_file_date(stem).is_some() {
+                    return None;
+                }
+                let video = VideoInfo::load_sidecar(path).ok()?;
+                (video.upload_date.as_str() < cutoff).then(|| stem.to_string())
+            })
+            .collect();
+        // Safe names have no dots, so "<stem>." can't match another episode
+        let belongs_to_old = |file_name: &str| {
+            undated_old.iter().any(|stem| {
+                file_name
+                    .strip_prefix(stem.as_str())
+                    .is_some_and(|rest| rest.starts_with('.') || rest == "-thumb.jpg")
+            })
+        };
+
+        for path in paths {
+            let Some(file_name) = path.file_name().and_then(|n| n.to_str()) else {
+                continue;
+            };
+            let is_old = match episode_file_date(file_name) {
+                Some(date) => date < cutoff,
+                None => belongs_to_old(file_name),
+            };
+            if !is_old {
+                continue;
+            }
+
+            if path.extension().and_then(|e| e.to_str()) == Some("strm")
+                && let Some(id) = fs::read_to_string(&path)
+                    .ok()
+                    .as_deref()
+                    .and_then(strm_video_id)
+            {
+                pruned_ids.push(id.to_string());
+            }
+            let _ = fs::remove_file(&path);
+        }
+
+        // Only succeeds when nothing else is left in the season
+        let _ = fs::remove_dir(&season_dir);
+    }
+
+    pruned_ids
+}
+
+/// Deletes the episode in `media_dir` whose .strm streams `video_id`, with its .nfo,
+/// thumbnail, sidecar and subtitles, and its season directory if that is left
+/// empty. Returns false if no .strm streams `video_id`.
+pub fn delete_episode(media_dir: &Path, video_id: &str) -> std::io::Result<bool> {
+    let strm_paths: Vec<PathBuf> = strm_files(media_dir)
+        .into_iter()
+        .filter(|path| {
+            fs::read_to_string(path).is_ok_and(|content| strm_video_id(&content) == Some(video_id))
+        })
+        .collect();
+
+    for strm_path in &strm_paths {
+        let (Some(season_dir), Some(stem)) = (
+            strm_path.parent(),
+            strm_path.file_stem().and_then(|s| s.to_str()),
+        ) else {
+            continue;
+        };
+        remove_episode_files(season_dir, stem)?;
+        // Only succeeds when nothing else is left in the season
+        let _ = fs::remove_dir(season_dir);
+    }
+
+    Ok(!strm_paths.is_empty())
+}
+
+/// Deletes the files of the episode named `stem` in `season_dir`: the .strm, .nfo,
+/// sidecar, subtitles and thumbnail.
+fn remove_episode_files(season_dir: &Path, stem: &str) -> std::io::Result<()> {
+    // Safe names have no dots, so "<stem>." can't match another episode
+    for entry in fs::read_dir(season_dir)?.flatten() {
+        let file_name = entry.file_name();
+        let belongs = file_name.to_str().is_some_and(|name| {
+            name.strip_prefix(stem)
+                .is_some_and(|rest| rest.starts_with('.') || rest == "-thumb.jpg")
+        });
+        if belongs {
+            fs::remove_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+// What ytstrm writes at the top of a channel directory
+const CHANNEL_FILES: [&str; 4] = ["tvshow.nfo", "poster.jpg", "landscape.jpg", "folder.jpg"];
+
+/// Deletes what ytstrm wrote for a channel: the episodes in its `Season N`
+/// directories, the archive season's season.nfo, the artwork and tvshow.nfo. The
+/// directories go too once nothing else is left in them, anything the user put
+/// there stays.
+pub fn remove_channel_files(media_dir: &Path) -> std::io::Result<()> {
+    let seasons = match fs::read_dir(media_dir) {
+        Ok(seasons) => seasons,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    for season_dir in seasons.flatten().map(|entry| entry.path()) {
+        let is_season = season_dir
+            .file_name()
+            .and_then(|n| n.to_str())
+            .is_some_and(|n| n.starts_with("Season "));
+        if !is_season || !season_dir.is_dir() {
+            continue;
+        }
+        for strm_path in fs::read_dir(&season_dir)?
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().and_then(|e| e.to_str()) == Some("strm"))
+        {
+            if let Some(stem) = strm_path.file_stem().and_then(|s| s.to_str()) {
+                remove_episode_files(&season_dir, stem)?;
+            }
+        }
+        let _ = fs::remove_file(season_dir.join("season.nfo"));
+        let _ = fs::remove_dir(&season_dir);
+    }
+
+    for name in CHANNEL_FILES {
+        let _ = fs::remove_file(media_dir.join(name));
+    }
+    let _ = fs::remove_dir(media_dir);
+    Ok(())
+}
+
+/// Bytes available to ytstrm on the disk holding `path`. A directory that doesn't
+/// exist yet is measured at its closest existing parent.
+pub fn free_space(path: &Path) -> std::io::Result<u64> {
+    let existing = path
+        .ancestors()
+        .find(|dir| dir.exists())
+        .unwrap_or(Path::new("."));
+    fs2::available_space(existing)
+}
+
+/// Video ids each channel already has a .strm for, so the per-video existence check
+/// doesn't have to stat network storage. A channel's ids are read from disk the
+/// first time it is checked and kept current as ytstrm writes and deletes episodes.
+#[derive(Default)]
+pub struct EpisodeIndex {
+    channels: Mutex<HashMap<String, HashSet<String>>>,
+}
+
+static EPISODE_INDEX: LazyLock<EpisodeIndex> = LazyLock::new(EpisodeIndex::default);
+
+pub fn episode_index() -> &'static EpisodeIndex {
+    &EPISODE_INDEX
+}
+
+impl EpisodeIndex {
+    pub async fn contains(&self, channel: &Channel, video_id: &str) -> bool {
+        if let Some(ids) = self.channels.lock().unwrap().get(&channel.id) {
+            return ids.contains(video_id);
+        }
+        // Read off the runtime and without the lock, other channels stay usable meanwhile
+        let channels = vec![channel.clone()];
+        let ids = tokio::task::spawn_blocking(move || materialized_video_ids(&channels))
+            .await
+            .unwrap_or_default();
+        // Another check may have loaded it first, its ids are at least as current
+        self.channels
+            .lock()
+            .unwrap()
+            .entry(channel.id.clone())
+            .or_insert(ids)
+            .contains(video_id)
+    }
+
+    pub fn insert(&self, channel_id: &str, video_id: &str) {
+        // Channels not loaded yet pick the new file up when they are
+        if let Some(ids) = self.channels.lock().unwrap().get_mut(channel_id) {
+            ids.insert(video_id.to_string());
+        }
+    }
+
+    pub fn remove(&self, channel_id: &str, video_ids: &[String]) {
+        if let Some(ids) = self.channels.lock().unwrap().get_mut(channel_id) {
+            for id in video_ids {
+                ids.remove(id);
+            }
+        }
+    }
+
+    /// Drops a channel's ids, e.g. after its directory was wiped; reloaded on next use.
+    pub fn forget_channel(&self, channel_id: &str) {
+        self.channels.lock().unwrap().remove(channel_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_channel;
+
+    #[tokio::test]
+    async fn episode_index_answers_without_the_filesystem_once_loaded() {
+        let dir = tempfile::tempdir().unwrap();
+        let channel = test_channel(dir.path());
+        let season = dir.path().join("Season 2024");
+        fs::create_dir_all(&season).unwrap();
+        fs::write(season.join("20240101 - A.strm"), "http://host/stream/abc").unwrap();
+
+        let index = EpisodeIndex::default();
+        assert!(index.contains(&channel, "abc").await);
+        assert!(!index.contains(&channel, "def").await);
+
+        // Answered from memory, the deleted file isn't looked at again
+        fs::remove_dir_all(&season).unwrap();
+        assert!(index.contains(&channel, "abc").await);
+        index.insert(&channel.id, "def");
+        assert!(index.contains(&channel, "def").await);
+        index.remove(&channel.id, &["abc".to_string()]);
+        assert!(!index.contains(&channel, "abc").await);
+
+        // Reloaded from disk after forgetting
+        index.forget_channel(&channel.id);
+        assert!(!index.contains(&channel, "def").await);
+    }
+
+    #[test]
+    fn rewrite_strm_urls_moves_episodes_to_the_new_address() {
+        let dir = tempfile::tempdir().unwrap();
+        let channel = test_channel(dir.path());
+        let season = dir.path().join("Season 2024");
+        fs::create_dir_all(&season).unwrap();
+        let old = season.join("20240101 - Old.strm");
+        let current = season.join("20240102 - Current.strm");
+        fs::write(&old, "http://192.168.1.5:8080/stream/abc\n").unwrap();
+        fs::write(&current, "https://new.example:9000/stream/def").unwrap();
+
+        let stream_url = |video_id: &str| format!("https://new.example:9000/stream/{}", video_id);
+        let channels = [channel];
+        assert_eq!(rewrite_strm_urls(&channels, stream_url), 1);
+        assert_eq!(
+            fs::read_to_string(&old).unwrap(),
+            "https://new.example:9000/stream/abc"
+        );
+        assert_eq!(
+            fs::read_to_string(&current).unwrap(),
+            "https://new.example:9000/stream/def"
+        );
+
+        // Nothing left to change the second time
+        assert_eq!(rewrite_strm_urls(&channels, stream_url), 0);
+    }
+
+    #[test]
+    fn remove_channel_files_keeps_what_ytstrm_did_not_write() {
+        let dir = tempfile::tempdir().unwrap();
+        let channel_dir = dir.path().join("channel");
+        let season = channel_dir.join("Season 2024");
+        fs::create_dir_all(&season).unwrap();
+        for name in [
+            "20240101 - Title.strm",
+            "20240101 - Title.nfo",
+            "20240101 - Title.ytstrm.json",
+            "20240101 - Title-thumb.jpg",
+            "20240101 - Title.en.vtt",
+        ] {
+            fs::write(season.join(name), "x").unwrap();
+        }
+        fs::write(channel_dir.join("tvshow.nfo"), "x").unwrap();
+        fs::write(channel_dir.join("poster.jpg"), "x").unwrap();
+        // Not written by ytstrm
+        fs::write(channel_dir.join("notes.txt"), "x").unwrap();
+        fs::create_dir_all(channel_dir.join("Extras")).unwrap();
+
+        remove_channel_files(&channel_dir).unwrap();
+
+        assert!(!season.exists());
+        assert!(!channel_dir.join("tvshow.nfo").exists());
+        assert!(!channel_dir.join("poster.jpg").exists());
+        assert!(channel_dir.join("notes.txt").exists());
+        assert!(channel_dir.join("Extras").exists());
+    }
+
+    #[test]
+    fn remove_channel_files_removes_an_emptied_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let channel_dir = dir.path().join("channel");
+        fs::create_dir_all(channel_dir.join("Season 2024")).unwrap();
+        fs::write(channel_dir.join("Season 2024/20240101 - A.strm"), "x").unwrap();
+
+        remove_channel_files(&channel_dir).unwrap();
+        assert!(!channel_dir.exists());
+        // Resetting a channel that was never synced is fine too
+        remove_channel_files(&channel_dir).unwrap();
+    }
+}
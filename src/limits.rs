@@ -0,0 +1,55 @@
+use std::sync::{Arc, LazyLock, Mutex};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// A semaphore whose size follows a config value. When the limit changes, a fresh
+/// semaphore replaces the old one; permits already handed out drain from the old
+/// one, so the cap may briefly be exceeded right after a change.
+#[derive(Default)]
+pub struct ConcurrencyLimit {
+    current: Mutex<Option<(usize, Arc<Semaphore>)>>,
+}
+
+impl ConcurrencyLimit {
+    /// Waits for a slot under `limit` (at least 1), held until the permit is dropped.
+    pub async fn acquire(&self, limit: usize) -> OwnedSemaphorePermit {
+        self.semaphore(limit)
+            .acquire_owned()
+            .await
+            .expect("limit semaphores are never closed")
+    }
+
+    /// A slot under `limit` if one is free right now.
+    pub fn try_acquire(&self, limit: usize) -> Option<OwnedSemaphorePermit> {
+        self.semaphore(limit).try_acquire_owned().ok()
+    }
+
+    fn semaphore(&self, limit: usize) -> Arc<Semaphore> {
+        let limit = limit.max(1);
+        let mut current = self.current.lock().unwrap();
+        match &*current {
+            Some((size, semaphore)) if *size == limit => semaphore.clone(),
+            _ => {
+                let semaphore = Arc::new(Semaphore::new(limit));
+                *current = Some((limit, semaphore.clone()));
+                semaphore
+            }
+        }
+    }
+}
+
+/// Process-wide caps on concurrent work, shared by syncs, the UI and maintenance.
+#[derive(Default)]
+pub struct Limits {
+    pub image_downloads: ConcurrencyLimit,
+    pub scan_windows: ConcurrencyLimit,
+    // yt-dlp runs that talk to YouTube, apart from playback
+    pub ytdlp: ConcurrencyLimit,
+    // yt-dlp runs serving a stream, kept apart so playback doesn't queue behind a scan
+    pub playback_ytdlp: ConcurrencyLimit,
+}
+
+static LIMITS: LazyLock<Limits> = LazyLock::new(Limits::default);
+
+pub fn limits() -> &'static Limits {
+    &LIMITS
+}
@@ -1,26 +1,44 @@
 mod api;
+mod auth;
 mod channel;
 mod config;
+mod filename;
+mod hooks;
+mod library;
+mod limits;
 mod manifest;
+mod metrics;
 mod migrations;
+mod mp4;
+mod notify;
+mod schedule;
+mod status;
 mod templates;
+mod ytdlp;
 
+use axum::Json;
 use axum::extract::State;
-use axum::response::Html;
-use axum::{Router, extract::Path, response::Response, routing::get};
-use config::{Channel, Config, Source, check_channels};
+use axum::http::{HeaderMap, StatusCode, header};
+use axum::response::{Html, IntoResponse};
+use axum::{Router, extract::Path, middleware, response::Response, routing::get};
+use axum_server::tls_rustls::RustlsConfig;
+use config::{Channel, Config, Source, check_channels, create_missing_channel_structures};
 use serde::Serialize;
 use std::collections::HashMap;
-use std::process::Stdio;
+use std::net::SocketAddr;
 use std::{path::PathBuf, sync::Arc};
 use tokio::net::TcpListener;
-use tokio::process::Command;
 use tokio::sync::RwLock;
-use tokio_util::io::ReaderStream;
-use tracing::info;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+use tracing::{error, info, warn};
+use tracing_subscriber::EnvFilter;
 
-use manifest::{ManifestCache, fetch_and_filter_manifest, maintain_manifest_cache};
+use manifest::{
+    CacheOptions, FetchError, ManifestCache, fetch_and_filter_manifest, maintain_manifest_cache,
+};
 use templates::{TemplateState, Templates};
+use ytdlp::{YTDLP_INSTALL_HINT, ytdlp_version};
 
 const IS_DEV: bool = cfg!(debug_assertions);
 
@@ -29,203 +47,486 @@ pub type ConfigState = Arc<RwLock<Config>>;
 pub struct AppState {
     config: ConfigState,
     templates: TemplateState,
+    // Cancelled on SIGTERM/SIGINT; syncs stop after their in-flight video
+    shutdown: CancellationToken,
+    // Syncs spawned from the UI, awaited before the process exits
+    tasks: TaskTracker,
+    // Why yt-dlp couldn't be run at startup; background tasks don't start then
+    ytdlp_error: Option<String>,
+    // What happened to an unreadable config.json at startup, shown on the settings page
+    config_recovery: Option<String>,
 }
 pub type AppStateArc = Arc<AppState>;
 
-#[tokio::main]
-async fn main() {
-    // Initialize logging
-    if IS_DEV {
-        tracing_subscriber::fmt()
-            .with_max_level(tracing::Level::INFO)
-            .with_target(true)
-            .with_thread_ids(true)
-            .with_file(true)
-            .with_line_number(true)
-            .init();
-    } else {
-        tracing_subscriber::fmt()
-            .with_max_level(tracing::Level::INFO)
-            .init();
+/// Sets up logging before anything else runs, so it is configured from the
+/// environment rather than config.json. `YTSTRM_LOG_FORMAT` picks `pretty`,
+/// `compact` or `json` output, and `RUST_LOG` the levels, e.g.
+/// `info,ytstrm::manifest=debug`. Without them logs are plain text at INFO.
+fn init_logging() {
+    let (filter, bad_filter) = match EnvFilter::try_from_default_env() {
+        Ok(filter) => (filter, None),
+        Err(e) => (
+            EnvFilter::new("info"),
+            std::env::var("RUST_LOG").ok().map(|value| (value, e)),
+        ),
+    };
+    let format = std::env::var("YTSTRM_LOG_FORMAT").unwrap_or_default();
+
+    // Dev builds also log where each line came from
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_thread_ids(IS_DEV)
+        .with_file(IS_DEV)
+        .with_line_number(IS_DEV);
+    match format.as_str() {
+        "json" => builder.json().init(),
+        "pretty" => builder.pretty().init(),
+        "compact" => builder.compact().init(),
+        _ => builder.init(),
     }
 
-    if let Err(e) = migrations::run_migrations() {
-        info!("Failed to run migrations: {}", e);
-        return;
+    if !matches!(format.as_str(), "" | "json" | "pretty" | "compact") {
+        warn!(
+            "Unknown YTSTRM_LOG_FORMAT {:?}, expected pretty, compact or json",
+            format
+        );
+    }
+    if let Some((value, e)) = bad_filter {
+        warn!("Ignoring invalid RUST_LOG {:?}: {}", value, e);
     }
+}
 
-    let config = Arc::new(RwLock::new(Config::load().unwrap()));
+#[tokio::main]
+async fn main() {
+    init_logging();
 
-    // Spawn background maintenance task
-    let config_clone = config.clone();
-    tokio::spawn(maintain_manifest_cache(config_clone));
+    let loaded = match migrations::run_migrations().await {
+        Ok(()) => Config::load(),
+        Err(e) => Err(e),
+    };
+    let (config, config_recovery) = match loaded {
+        Ok(config) => (config, None),
+        Err(e) => {
+            let config_path = Config::config_path();
+            error!("==============================================================");
+            error!("Config at {:?} is unreadable: {:#}", config_path, e);
+            let (backup_path, config) = Config::recover_from_corrupt(&config_path)
+                .expect("Failed to recover from corrupt config");
+            error!("Moved it to {:?} and started with defaults", backup_path);
+            error!("==============================================================");
+            let recovery = format!(
+                "config.json couldn't be read ({:#}). It was moved to {} and ytstrm started with the default settings.",
+                e,
+                backup_path.display()
+            );
+            (config, Some(recovery))
+        }
+    };
+    let config = Arc::new(RwLock::new(config));
 
-    let config_clone = config.clone();
-    tokio::spawn(async move {
-        let _ = check_channels(config_clone).await;
-    });
+    let shutdown = CancellationToken::new();
+    let tasks = TaskTracker::new();
+    tokio::spawn(shutdown_signal(shutdown.clone()));
+
+    let ytdlp_error = match ytdlp_version().await {
+        Ok(version) => {
+            info!("Using yt-dlp {}", version);
+            None
+        }
+        Err(e) => {
+            error!("==============================================================");
+            error!("{}", e);
+            error!("{}", YTDLP_INSTALL_HINT);
+            error!("Background tasks are disabled until then");
+            error!("==============================================================");
+            Some(e.to_string())
+        }
+    };
+
+    // Every background task needs yt-dlp, the UI still starts to show the problem
+    if ytdlp_error.is_none() {
+        // Spawn background maintenance task
+        let config_clone = config.clone();
+        tasks.spawn(maintain_manifest_cache(config_clone, shutdown.clone()));
+
+        let config_clone = config.clone();
+        let shutdown_clone = shutdown.clone();
+        tasks.spawn(async move {
+            // Channels whose directory was deleted on disk get their artwork and NFO
+            // back, before the first sweep starts writing into the same directories
+            let settings = config_clone.read().await.clone();
+            create_missing_channel_structures(settings.channels.clone(), &settings).await;
+            let _ = check_channels(config_clone, shutdown_clone).await;
+        });
+    }
 
     let templates = Arc::new(Templates::new().unwrap());
 
     let app_state = Arc::new(AppState {
         config: config.clone(),
         templates: templates.clone(),
+        shutdown: shutdown.clone(),
+        tasks: tasks.clone(),
+        ytdlp_error,
+        config_recovery,
     });
 
-    let app = Router::new()
+    // The UI, API and metrics need the auth token when one is set; streams stay open
+    // for Jellyfin
+    let protected = Router::new()
         .route("/", get(index_handler))
         .merge(channel::routes())
-        .route("/stream/{id}", get(stream_youtube))
         .nest("/api", api::routes())
+        // Runs yt-dlp when the manifest isn't cached, like the UI's actions
+        .route("/stream/{id}/info.json", get(stream_info))
+        .route("/metrics", get(metrics::metrics_handler))
+        .route_layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            auth::require_auth,
+        ));
+
+    let app = Router::new()
+        .merge(protected)
+        .route("/login", get(auth::login_page).post(auth::login))
+        .route("/stream/{id}", get(stream_youtube))
         .with_state(app_state);
 
-    info!("Starting server on 127.0.0.1:8080");
-    let listener = TcpListener::bind("0.0.0.0:8080").await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    let tls_paths = config
+        .read()
+        .await
+        .tls_paths()
+        .map(|(cert, key)| (cert.to_path_buf(), key.to_path_buf()));
+    if let Some((cert_path, key_path)) = tls_paths {
+        let tls_config = RustlsConfig::from_pem_file(&cert_path, &key_path)
+            .await
+            .expect("Failed to load TLS certificate and key");
+
+        let handle = axum_server::Handle::new();
+        let shutdown_handle = handle.clone();
+        let shutdown_clone = shutdown.clone();
+        tokio::spawn(async move {
+            shutdown_clone.cancelled().await;
+            shutdown_handle.graceful_shutdown(None);
+        });
+
+        info!("Starting HTTPS server on 127.0.0.1:8080");
+        axum_server::bind_rustls(SocketAddr::from(([0, 0, 0, 0], 8080)), tls_config)
+            .handle(handle)
+            .serve(app.into_make_service())
+            .await
+            .unwrap();
+    } else {
+        info!("Starting server on 127.0.0.1:8080");
+        let listener = TcpListener::bind("0.0.0.0:8080").await.unwrap();
+        axum::serve(listener, app)
+            .with_graceful_shutdown(shutdown.clone().cancelled_owned())
+            .await
+            .unwrap();
+    }
+
+    info!("Server stopped, waiting for background tasks to finish");
+    tasks.close();
+    tasks.wait().await;
+    info!("Shutdown complete");
+}
+
+async fn shutdown_signal(shutdown: CancellationToken) {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to install Ctrl+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+
+    info!("Shutdown requested, finishing in-flight work");
+    shutdown.cancel();
 }
 
 async fn stream_youtube(
     State(state): State<AppStateArc>,
     Path(video_id): Path<String>,
+    headers: HeaderMap,
 ) -> Response {
     info!("Streaming video: {}", video_id);
+    let accepts_gzip = accepts_gzip(&headers);
 
     let config = state.config.read().await;
     let cache_dir = PathBuf::from(&config.jellyfin_media_path).join("manifests");
 
     // Try to load from cache first
-    if let Ok(cache) = ManifestCache::load(&video_id, &cache_dir) {
-        if cache.is_valid() {
-            info!("Serving cached manifest for {}", video_id);
-            return Response::builder()
-                .status(200)
-                .header("Content-Type", "application/vnd.apple.mpegurl")
-                .header("Access-Control-Allow-Origin", "*")
-                .header("Content-Length", cache.content.len().to_string())
-                .header(
-                    "Content-Disposition",
-                    "attachment; filename=\"playlist.m3u8\"",
-                )
-                .header("Cache-Control", "no-cache")
-                .header("Pragma", "no-cache")
-                .header("Expires", "0")
-                .body(axum::body::Body::from(cache.content))
-                .unwrap();
-        }
+    if let Ok(cache) = ManifestCache::load(&video_id, &cache_dir)
+        && cache.is_valid(config.max_manifest_cache_age_secs)
+    {
+        info!("Serving cached manifest for {}", video_id);
+        metrics::metrics().manifest_cache_hit();
+        manifest::mark_served(&video_id);
+        return manifest_response(cache.content, cache.gzipped, accepts_gzip, "no-cache");
     }
 
-    match fetch_and_filter_manifest(&video_id, &cache_dir, true, &None).await {
+    metrics::metrics().manifest_cache_miss();
+    let network = ytdlp::NetworkOptions::from_config(&config).for_playback();
+    let cache_options = CacheOptions::from_config(&config);
+    let fallback_format = config.fallback_format_selector.clone();
+    let geo_channels: Vec<Channel> = config
+        .channels
+        .iter()
+        .filter(|c| c.geo_proxy_url.is_some())
+        .cloned()
+        .collect();
+    drop(config);
+    let fetched = fetch_manifest(
+        &video_id,
+        &cache_dir,
+        cache_options,
+        &network,
+        &geo_channels,
+    )
+    .await;
+
+    match fetched {
         Ok(manifest) => {
             info!("Sending manifest response with length: {}", manifest.len());
-            Response::builder()
-                .status(200)
-                .header("Content-Type", "application/vnd.apple.mpegurl")
-                .header("Access-Control-Allow-Origin", "*")
-                .header("Content-Length", manifest.len().to_string())
-                .header(
-                    "Content-Disposition",
-                    "attachment; filename=\"playlist.m3u8\"",
-                )
-                .header(
-                    "Cache-Control",
-                    "no-cache, no-store, must-revalidate, must-validate",
-                )
-                .header("Pragma", "no-cache")
-                .header("Expires", "0")
-                .body(axum::body::Body::from(manifest))
-                .unwrap()
+            manifest::mark_served(&video_id);
+            let gzipped = cache_options
+                .compress
+                .then(|| manifest::gzip(&manifest).ok())
+                .flatten();
+            manifest_response(
+                manifest,
+                gzipped,
+                accepts_gzip,
+                "no-cache, no-store, must-revalidate, must-validate",
+            )
+        }
+        // The MP4 fallback comes from the same servers, so it would be refused as well
+        Err(e)
+            if matches!(
+                e.downcast_ref::<FetchError>(),
+                Some(FetchError::RateLimited | FetchError::GeoBlocked | FetchError::Removed)
+            ) =>
+        {
+            playback_error_response(&video_id, &e)
         }
         Err(e) => {
             info!(
                 "Failed to fetch/filter manifest: {}, falling back to MP4",
                 e
             );
-            direct_mp4_streaming(
+            mp4::direct_mp4_streaming(
                 &format!("https://www.youtube.com/watch?v={}", video_id),
                 &video_id,
+                &network,
+                &fallback_format,
+                headers.get(header::RANGE),
             )
             .await
+            .unwrap_or_else(|e| playback_error_response(&video_id, &e))
         }
     }
 }
 
-async fn direct_mp4_streaming(url: &str, video_id: &str) -> Response {
-    info!("Attempting direct MP4 streaming");
-    let process = match Command::new("yt-dlp")
-        .args([
-            "-o",
-            "-",
-            "-f",
-            "22/18/best[ext=mp4]",
-            "--no-playlist",
-            "--cookies",
-            "cookies.txt",
-        ])
-        .arg(if IS_DEV { "-v" } else { "--no-warnings" })
-        .arg(url)
-        .stdout(Stdio::piped())
-        .spawn()
+/// The response for a video that can't be streamed at all, telling players whether
+/// to try again: 404 for a removed video, 451 for a region-blocked one, and 503
+/// with Retry-After for anything that may pass, so clients don't remember the
+/// video as broken.
+fn playback_error_response(video_id: &str, e: &anyhow::Error) -> Response {
+    info!("Can't stream {}: {}", video_id, e);
+    let (status, retry_after) = match e.downcast_ref::<FetchError>() {
+        Some(FetchError::Removed) => (StatusCode::NOT_FOUND, None),
+        Some(FetchError::GeoBlocked) => (StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS, None),
+        Some(FetchError::RateLimited) => (StatusCode::SERVICE_UNAVAILABLE, Some("60")),
+        _ => (StatusCode::SERVICE_UNAVAILABLE, Some("30")),
+    };
+    let mut response = (status, format!("Can't play {}: {}", video_id, e)).into_response();
+    if let Some(seconds) = retry_after {
+        response.headers_mut().insert(
+            header::RETRY_AFTER,
+            header::HeaderValue::from_static(seconds),
+        );
+    }
+    response
+}
+
+/// Fetches, filters and caches a video's manifest. If it is region-blocked here, the
+/// fetch is retried through the geo proxy of a channel in `geo_channels` that has the
+/// video.
+async fn fetch_manifest(
+    video_id: &str,
+    cache_dir: &std::path::Path,
+    cache_options: CacheOptions,
+    network: &ytdlp::NetworkOptions,
+    geo_channels: &[Channel],
+) -> anyhow::Result<String> {
+    let fetched =
+        fetch_and_filter_manifest(video_id, cache_dir, Some(cache_options), &None, network).await;
+
+    let mut geo_network = None;
+    if let Err(e) = &fetched
+        && matches!(e.downcast_ref::<FetchError>(), Some(FetchError::GeoBlocked))
     {
-        Ok(process) => process,
+        for channel in geo_channels {
+            if library::episode_index().contains(channel, video_id).await {
+                geo_network = channel.geo_network(network);
+                break;
+            }
+        }
+    }
+    if let Some(geo_network) = geo_network {
+        info!(
+            "{} is region-blocked, retrying through the geo proxy",
+            video_id
+        );
+        return fetch_and_filter_manifest(
+            video_id,
+            cache_dir,
+            Some(cache_options),
+            &None,
+            &geo_network,
+        )
+        .await;
+    }
+    fetched
+}
+
+/// The renditions and audio tracks of the manifest `/stream/{id}` serves, as JSON,
+/// for clients that pick a stream themselves. Uses the cached manifest while it's
+/// valid, and fetches and caches one otherwise.
+async fn stream_info(State(state): State<AppStateArc>, Path(video_id): Path<String>) -> Response {
+    let config = state.config.read().await;
+    let cache_dir = PathBuf::from(&config.jellyfin_media_path).join("manifests");
+
+    if let Ok(cache) = ManifestCache::load(&video_id, &cache_dir)
+        && cache.is_valid(config.max_manifest_cache_age_secs)
+    {
+        return Json(manifest::manifest_info(&video_id, &cache.content)).into_response();
+    }
+
+    let network = ytdlp::NetworkOptions::from_config(&config).for_playback();
+    let cache_options = CacheOptions::from_config(&config);
+    let geo_channels: Vec<Channel> = config
+        .channels
+        .iter()
+        .filter(|c| c.geo_proxy_url.is_some())
+        .cloned()
+        .collect();
+    drop(config);
+
+    match fetch_manifest(
+        &video_id,
+        &cache_dir,
+        cache_options,
+        &network,
+        &geo_channels,
+    )
+    .await
+    {
+        Ok(content) => Json(manifest::manifest_info(&video_id, &content)).into_response(),
         Err(e) => {
-            info!("Failed to spawn yt-dlp: {}", e);
-            return Response::builder()
-                .status(500)
-                .body(axum::body::Body::empty())
-                .unwrap();
+            let status = match e.downcast_ref::<FetchError>() {
+                Some(FetchError::RateLimited) => StatusCode::SERVICE_UNAVAILABLE,
+                Some(FetchError::GeoBlocked) => StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS,
+                Some(FetchError::Removed) => StatusCode::NOT_FOUND,
+                _ => StatusCode::BAD_GATEWAY,
+            };
+            info!("Failed to fetch manifest info for {}: {}", video_id, e);
+            (status, e.to_string()).into_response()
         }
-    };
+    }
+}
+
+/// Whether the client's Accept-Encoding allows gzip (and doesn't refuse it with q=0).
+fn accepts_gzip(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|coding| {
+            let mut parts = coding.split(';').map(str::trim);
+            let name = parts.next().unwrap_or_default();
+            (name.eq_ignore_ascii_case("gzip") || name == "*")
+                && !parts.any(|param| matches!(param, "q=0" | "q=0.0" | "q=0.00" | "q=0.000"))
+        })
+}
 
-    let stdout = process.stdout.unwrap();
-    let stream = ReaderStream::new(stdout);
+/// Builds the m3u8 response, sending the gzipped bytes when we have them and the
+/// client takes gzip, the plain text otherwise. Content-Length is taken from the
+/// bytes actually sent, whichever they are.
+fn manifest_response(
+    content: String,
+    gzipped: Option<Vec<u8>>,
+    accepts_gzip: bool,
+    cache_control: &str,
+) -> Response {
+    let renditions = manifest::renditions(&content);
+    let (body, encoding) = match gzipped {
+        Some(bytes) if accepts_gzip => (bytes, Some("gzip")),
+        _ => (content.into_bytes(), None),
+    };
 
-    Response::builder()
-        .header("Content-Type", "video/mp4")
+    let mut response = Response::builder()
+        .status(200)
+        .header("Content-Type", "application/vnd.apple.mpegurl")
+        .header("Access-Control-Allow-Origin", "*")
+        .header("Content-Length", body.len().to_string())
+        .header("X-Ytstrm-Renditions", renditions)
         .header(
             "Content-Disposition",
-            format!("inline; filename=\"{}.mp4\"", video_id),
+            "attachment; filename=\"playlist.m3u8\"",
         )
-        .header("Accept-Ranges", "none")
-        .header("Cache-Control", "no-cache")
-        .body(axum::body::Body::from_stream(stream))
-        .unwrap()
+        .header("Cache-Control", cache_control)
+        .header("Pragma", "no-cache")
+        .header("Expires", "0")
+        .header("Vary", "Accept-Encoding");
+    if let Some(encoding) = encoding {
+        response = response.header("Content-Encoding", encoding);
+    }
+    response.body(axum::body::Body::from(body)).unwrap()
 }
 
 #[derive(Debug, Serialize)]
 struct ChannelWithCount<'a> {
     channel: &'a Channel,
     video_count: usize,
+    // Not checked for several intervals, the next sync has a lot to catch up on
+    stale: bool,
+    // When last_result was recorded, in local time
+    last_synced: Option<String>,
+}
+
+fn last_synced(channel: &Channel) -> Option<String> {
+    let result = channel.last_result.as_ref()?;
+    let time = chrono::DateTime::<chrono::Local>::from(result.timestamp);
+    Some(time.format("%Y-%m-%d %H:%M").to_string())
 }
 
 async fn index_handler(State(state): State<AppStateArc>) -> Result<Html<String>, ()> {
     let config_guard = state.config.read().await;
 
     // Count .strm files in each channel's directory
-    let mut video_counts: HashMap<String, usize> = HashMap::new();
-
-    for channel in &config_guard.channels {
-        let mut count = 0;
-        if let Ok(seasons) = std::fs::read_dir(&channel.media_dir) {
-            for season in seasons.flatten() {
-                if season.file_type().map(|ft| ft.is_dir()).unwrap_or(false) {
-                    if let Ok(files) = std::fs::read_dir(season.path()) {
-                        count += files
-                            .flatten()
-                            .filter(|entry| {
-                                entry
-                                    .path()
-                                    .extension()
-                                    .and_then(|ext| ext.to_str())
-                                    .map(|ext| ext == "strm")
-                                    .unwrap_or(false)
-                            })
-                            .count();
-                    }
-                }
-            }
-        }
-        video_counts.insert(channel.id.clone(), count);
-    }
+    let video_counts: HashMap<String, usize> = config_guard
+        .channels
+        .iter()
+        .map(|channel| {
+            let count = library::strm_files(&channel.media_dir).len();
+            (channel.id.clone(), count)
+        })
+        .collect();
 
     // Filter channels and playlists
     let channels: Vec<ChannelWithCount> = config_guard
@@ -235,6 +536,8 @@ async fn index_handler(State(state): State<AppStateArc>) -> Result<Html<String>,
         .map(|c| ChannelWithCount {
             channel: c,
             video_count: video_counts.get(&c.id).copied().unwrap_or(0),
+            stale: c.is_stale(&config_guard),
+            last_synced: last_synced(c),
         })
         .collect();
 
@@ -245,6 +548,8 @@ async fn index_handler(State(state): State<AppStateArc>) -> Result<Html<String>,
         .map(|c| ChannelWithCount {
             channel: c,
             video_count: video_counts.get(&c.id).copied().unwrap_or(0),
+            stale: c.is_stale(&config_guard),
+            last_synced: last_synced(c),
         })
         .collect();
 
@@ -256,11 +561,175 @@ async fn index_handler(State(state): State<AppStateArc>) -> Result<Html<String>,
                 config => &*config_guard,
                 channels => channels,
                 playlists => playlists,
+                ytdlp_error => state.ytdlp_error,
+                config_recovery => state.config_recovery,
+                ytdlp_install_hint => YTDLP_INSTALL_HINT,
             },
         )
         .map_err(|err| {
             info!("Failed to render template: {}", err);
-            ()
         })?;
     Ok(Html(html))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ChannelResult;
+    use axum::body::{Body, to_bytes};
+    use axum::http::Request;
+    use tower::ServiceExt;
+
+    async fn length_and_body(response: Response) -> (usize, Vec<u8>) {
+        let length = response.headers()[header::CONTENT_LENGTH]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (length, body.to_vec())
+    }
+
+    #[tokio::test]
+    async fn manifest_content_length_counts_the_bytes_sent() {
+        let content = "#EXTM3U\n#EXT-X-MEDIA:TYPE=AUDIO,NAME=\"Français ☕\"\n".to_string();
+        assert!(content.len() > content.chars().count());
+        let gzipped = manifest::gzip(&content).unwrap();
+
+        let response = manifest_response(content.clone(), None, true, "no-cache");
+        let (length, body) = length_and_body(response).await;
+        assert_eq!(body, content.as_bytes());
+        assert_eq!(length, body.len());
+
+        let response = manifest_response(content.clone(), Some(gzipped.clone()), true, "no-cache");
+        assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+        let (length, body) = length_and_body(response).await;
+        assert_eq!(body, gzipped);
+        assert_eq!(length, body.len());
+
+        // Clients without gzip get the plain text even when it was cached compressed
+        let response = manifest_response(content.clone(), Some(gzipped), false, "no-cache");
+        assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+        let (length, body) = length_and_body(response).await;
+        assert_eq!(body, content.as_bytes());
+        assert_eq!(length, body.len());
+    }
+
+    #[tokio::test]
+    async fn info_json_lists_the_served_renditions() {
+        let dir = tempfile::tempdir().unwrap();
+        let youtube_manifest = r#"#EXTM3U
+#EXT-X-MEDIA:URI="https://manifest.googlevideo.com/a/233",TYPE=AUDIO,GROUP-ID="233",LANGUAGE="en",NAME="English",DEFAULT=YES
+#EXT-X-MEDIA:URI="https://manifest.googlevideo.com/a/234",TYPE=AUDIO,GROUP-ID="234",LANGUAGE="en",NAME="English",DEFAULT=YES
+#EXT-X-STREAM-INF:BANDWIDTH=500000,CODECS="avc1.4d401e",RESOLUTION=640x360,AUDIO="233"
+https://manifest.googlevideo.com/v/360/expire/1900000000/
+#EXT-X-STREAM-INF:BANDWIDTH=5000000,CODECS="avc1.640028",RESOLUTION=1920x1080,AUDIO="234"
+https://manifest.googlevideo.com/v/1080/expire/1900000000/
+#EXT-X-STREAM-INF:BANDWIDTH=2500000,CODECS="avc1.4d401f",RESOLUTION=1280x720,AUDIO="234"
+https://manifest.googlevideo.com/v/720/expire/1900000000/
+"#;
+        let filtered = manifest::filter_and_modify_manifest(youtube_manifest.to_string());
+        ManifestCache::new("abc", filtered)
+            .save(&dir.path().join("manifests"), false)
+            .unwrap();
+
+        let state = Arc::new(AppState {
+            config: Arc::new(RwLock::new(Config {
+                jellyfin_media_path: dir.path().to_path_buf(),
+                ..Config::default()
+            })),
+            templates: Arc::new(Templates::new().unwrap()),
+            shutdown: CancellationToken::new(),
+            tasks: TaskTracker::new(),
+            ytdlp_error: None,
+            config_recovery: None,
+        });
+        let app = Router::new()
+            .route("/stream/{id}/info.json", get(stream_info))
+            .route("/stream/{id}", get(stream_youtube))
+            .with_state(state);
+        let get_body = |uri: &'static str| {
+            let app = app.clone();
+            async move {
+                let response = app
+                    .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+                    .await
+                    .unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+                to_bytes(response.into_body(), usize::MAX).await.unwrap()
+            }
+        };
+
+        let served = String::from_utf8(get_body("/stream/abc").await.to_vec()).unwrap();
+        let info: serde_json::Value =
+            serde_json::from_slice(&get_body("/stream/abc/info.json").await).unwrap();
+
+        let served_uris: Vec<&str> = served
+            .lines()
+            .skip_while(|line| !line.starts_with("#EXT-X-STREAM-INF:"))
+            .filter(|line| !line.starts_with('#'))
+            .collect();
+        let info_uris: Vec<&str> = info["renditions"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|rendition| rendition["uri"].as_str().unwrap())
+            .collect();
+        assert_eq!(info_uris, served_uris);
+        let heights: Vec<u64> = info["renditions"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|rendition| rendition["height"].as_u64().unwrap())
+            .collect();
+        assert_eq!(heights, [1080, 720, 360]);
+        assert_eq!(
+            info["audio_tracks"].as_array().unwrap().len(),
+            served.matches("#EXT-X-MEDIA:").count()
+        );
+        assert_eq!(info["expires"], 1900000000);
+    }
+
+    #[tokio::test]
+    async fn index_shows_cancelled_syncs_apart_from_finished_ones() {
+        let dir = tempfile::tempdir().unwrap();
+        let sync_result = |cancelled| ChannelResult::new(&Ok(2), cancelled);
+        let channels = vec![
+            Channel {
+                id: "finished".to_string(),
+                last_result: Some(sync_result(false)),
+                ..config::test_channel(&dir.path().join("finished"))
+            },
+            Channel {
+                id: "cancelled".to_string(),
+                last_result: Some(sync_result(true)),
+                ..config::test_channel(&dir.path().join("cancelled"))
+            },
+        ];
+        let state = Arc::new(AppState {
+            config: Arc::new(RwLock::new(Config {
+                channels,
+                ..Config::default()
+            })),
+            templates: Arc::new(Templates::new().unwrap()),
+            shutdown: CancellationToken::new(),
+            tasks: TaskTracker::new(),
+            ytdlp_error: None,
+            config_recovery: None,
+        });
+        let app = Router::new()
+            .route("/", get(index_handler))
+            .with_state(state);
+
+        let response = app
+            .oneshot(Request::get("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let html = String::from_utf8(body.to_vec()).unwrap();
+        assert_eq!(html.matches("Cancelled after 2 added").count(), 1);
+        // The finished sync's badge, and the cancelled one's
+        assert_eq!(html.matches("2 added").count(), 2);
+    }
+}
@@ -1,19 +1,41 @@
 use anyhow::{Result, anyhow};
-use reqwest::Client;
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use serde::Serialize;
 use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::fs;
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
-use tokio::process::Command;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
 use tracing::info;
 
 use crate::ConfigState;
-use crate::config::{ProgressSender, send_cmd_output_progress};
+use crate::config::{Channel, Config, ProgressSender, SyncProgress, VideoInfo, sleep_or_shutdown};
+use crate::library::{materialized_video_ids, strm_files, strm_video_id};
+use crate::metrics::metrics;
+use crate::ytdlp::{NetworkOptions, is_geo_blocked, is_rate_limited, is_removed};
 
 pub struct ManifestCache {
     pub video_id: String,
     pub content: String,
     pub expires: u64,
+    // When the manifest was fetched (file mtime for cached ones), unix seconds
+    pub fetched_at: u64,
+    // The file's bytes when it was stored compressed, ready to send as Content-Encoding: gzip
+    pub gzipped: Option<Vec<u8>>,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
 }
 
 impl ManifestCache {
@@ -40,23 +62,44 @@ impl ManifestCache {
             video_id: video_id.to_string(),
             content,
             expires,
+            fetched_at: now_secs(),
+            gzipped: None,
         }
     }
 
-    pub fn is_valid(&self) -> bool {
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
-
+    pub fn is_valid(&self, max_age_secs: u64) -> bool {
         // Consider it invalid 5 minutes before actual expiration
-        self.expires > (now + 300)
+        self.expires > (now_secs() + 300) && !self.is_too_old(max_age_secs)
     }
 
-    pub fn save(&self, cache_dir: &Path) -> std::io::Result<()> {
+    /// Wall-clock cap that holds even if the expiry token was parsed wrong
+    pub fn is_too_old(&self, max_age_secs: u64) -> bool {
+        now_secs().saturating_sub(self.fetched_at) > max_age_secs
+    }
+
+    pub fn save(&self, cache_dir: &Path, compress: bool) -> std::io::Result<()> {
         fs::create_dir_all(cache_dir)?;
-        let path = cache_dir.join(format!("{}.m3u8", self.video_id));
-        fs::write(path, &self.content)
+        let (extension, other_extension) = if compress {
+            ("m3u8.gz", "m3u8")
+        } else {
+            ("m3u8", "m3u8.gz")
+        };
+        let path = cache_dir.join(format!("{}.{}", self.video_id, extension));
+        let bytes = if compress {
+            gzip(&self.content)?
+        } else {
+            self.content.clone().into_bytes()
+        };
+        // Rename into place so a reader never sees a partially written manifest
+        let tmp_path = cache_dir.join(format!("{}.{}.tmp", self.video_id, extension));
+        fs::write(&tmp_path, bytes)?;
+        fs::rename(tmp_path, path)?;
+
+        // Drop the copy in the other format, load() would prefer a stale .gz
+        match fs::remove_file(cache_dir.join(format!("{}.{}", self.video_id, other_extension))) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
     }
 
     pub fn save_original(&self, cache_dir: &Path) -> std::io::Result<()> {
@@ -65,39 +108,356 @@ impl ManifestCache {
         fs::write(path, &self.content)
     }
 
+    /// Loads the cached manifest, compressed or not, whichever `save` last wrote.
     pub fn load(video_id: &str, cache_dir: &Path) -> std::io::Result<Self> {
-        let path = cache_dir.join(format!("{}.m3u8", video_id));
-        let content = fs::read_to_string(path)?;
-        Ok(Self::new(video_id, content))
+        let gz_path = cache_dir.join(format!("{}.m3u8.gz", video_id));
+        let (path, content, gzipped) = match fs::read(&gz_path) {
+            Ok(bytes) => (gz_path, gunzip(&bytes)?, Some(bytes)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let path = cache_dir.join(format!("{}.m3u8", video_id));
+                let content = fs::read_to_string(&path)?;
+                (path, content, None)
+            }
+            Err(e) => return Err(e),
+        };
+        let fetched_at = fs::metadata(&path)?
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        Ok(Self {
+            fetched_at,
+            gzipped,
+            ..Self::new(video_id, content)
+        })
+    }
+}
+
+pub fn gzip(content: &str) -> std::io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(content.as_bytes())?;
+    encoder.finish()
+}
+
+fn gunzip(bytes: &[u8]) -> std::io::Result<String> {
+    let mut content = String::new();
+    GzDecoder::new(bytes).read_to_string(&mut content)?;
+    Ok(content)
+}
+
+/// The video id a cache directory entry holds the manifest for, `None` for
+/// sidecars (`<id>.original.m3u8`, `<id>.meta`...) and anything else that isn't
+/// exactly `<id>.m3u8` or `<id>.m3u8.gz`.
+fn cached_video_id(file_name: &str) -> Option<&str> {
+    let video_id = file_name
+        .strip_suffix(".m3u8.gz")
+        .or_else(|| file_name.strip_suffix(".m3u8"))?;
+    // YouTube ids are letters, digits, - and _, so a dot means a sidecar
+    let is_video_id = !video_id.is_empty()
+        && video_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    is_video_id.then_some(video_id)
+}
+
+/// Failures that callers react to differently from a generic fetch error. They
+/// travel inside the `anyhow::Error`, so check with `downcast_ref::<FetchError>()`.
+#[derive(Debug)]
+pub enum FetchError {
+    /// YouTube answered 429 or yt-dlp was refused for now; back off instead of
+    /// retrying straight away
+    RateLimited,
+    /// Any other non-2xx response for the manifest URL
+    Http(u16),
+    /// yt-dlp says the video isn't available in the server's region
+    GeoBlocked,
+    /// Filtering left no video stream or no audio, so it would play as a black screen
+    NoStreams,
+    /// yt-dlp says the video was deleted or made private
+    Removed,
+}
+
+impl FetchError {
+    /// The failure a failed yt-dlp run's stderr points to, if it is one of ours.
+    pub fn from_stderr(stderr: &[u8]) -> Option<FetchError> {
+        if is_geo_blocked(stderr) {
+            Some(FetchError::GeoBlocked)
+        } else if is_rate_limited(stderr) {
+            Some(FetchError::RateLimited)
+        } else if is_removed(stderr) {
+            Some(FetchError::Removed)
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FetchError::RateLimited => write!(f, "Rate limited by YouTube"),
+            FetchError::Http(status) => write!(f, "Manifest request failed with HTTP {}", status),
+            FetchError::GeoBlocked => write!(f, "Not available in the server's region"),
+            FetchError::NoStreams => write!(f, "Manifest has no playable video and audio streams"),
+            FetchError::Removed => write!(f, "Removed or made private on YouTube"),
+        }
+    }
+}
+
+impl std::error::Error for FetchError {}
+
+/// Every manifest currently in the cache, skipping sidecar files.
+pub fn list_cached(cache_dir: &Path) -> Vec<ManifestCache> {
+    let Ok(files) = fs::read_dir(cache_dir) else {
+        return Vec::new();
+    };
+    let mut caches: Vec<ManifestCache> = files
+        .flatten()
+        .filter_map(|file| {
+            let file_name = file.file_name().into_string().ok()?;
+            ManifestCache::load(cached_video_id(&file_name)?, cache_dir).ok()
+        })
+        .collect();
+    caches.sort_by(|a, b| a.video_id.cmp(&b.video_id));
+    caches
+}
+
+/// Deletes the cached manifest for `video_id`, or every one when it's `None`, along
+/// with the original and temp files saved next to it. Returns the files removed.
+pub fn clear_cached(cache_dir: &Path, video_id: Option<&str>) -> std::io::Result<usize> {
+    let files = match fs::read_dir(cache_dir) {
+        Ok(files) => files,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+
+    let mut removed = 0;
+    for file in files.flatten() {
+        let Ok(file_name) = file.file_name().into_string() else {
+            continue;
+        };
+        let Some(id) = [
+            ".original.m3u8",
+            ".m3u8.gz.tmp",
+            ".m3u8.tmp",
+            ".m3u8.gz",
+            ".m3u8",
+        ]
+        .iter()
+        .find_map(|suffix| file_name.strip_suffix(suffix)) else {
+            continue;
+        };
+        if video_id.is_none_or(|video_id| video_id == id) {
+            fs::remove_file(file.path())?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+// When this process last handed each manifest to a player. Maintenance rewrites the
+// files as it refreshes them, so their own timestamps don't say when they were used
+static LAST_SERVED: LazyLock<Mutex<HashMap<String, SystemTime>>> = LazyLock::new(Mutex::default);
+
+/// Records that a player just got `video_id`'s manifest, for `evict_manifests`.
+pub fn mark_served(video_id: &str) {
+    LAST_SERVED
+        .lock()
+        .unwrap()
+        .insert(video_id.to_string(), SystemTime::now());
+}
+
+/// Deletes cached manifests, least recently used first, until at most `max` are
+/// left. Recency is when a player last got the manifest since startup, or for ones
+/// not served since then, the file's access time (or modification time where access
+/// times aren't kept). Manifests of videos in `keep` are never deleted. Returns how
+/// many manifests were removed.
+pub fn evict_manifests(cache_dir: &Path, max: usize, keep: &HashSet<String>) -> usize {
+    let Ok(files) = fs::read_dir(cache_dir) else {
+        return 0;
+    };
+    let mut cached: Vec<(String, Option<SystemTime>, SystemTime)> = files
+        .flatten()
+        .filter_map(|file| {
+            let file_name = file.file_name().into_string().ok()?;
+            let video_id = cached_video_id(&file_name)?.to_string();
+            let metadata = file.metadata().ok()?;
+            let touched = metadata.accessed().or_else(|_| metadata.modified()).ok()?;
+            Some((video_id, None, touched))
+        })
+        .collect();
+    if cached.len() <= max {
+        return 0;
+    }
+
+    {
+        let served = LAST_SERVED.lock().unwrap();
+        for (video_id, served_at, _) in &mut cached {
+            *served_at = served.get(video_id.as_str()).copied();
+        }
+    }
+    let excess = cached.len() - max;
+    cached.retain(|(video_id, _, _)| !keep.contains(video_id));
+    // Never served since startup sorts first, then oldest
+    cached.sort_by_key(|(_, served_at, touched)| (*served_at, *touched));
+
+    let mut removed = 0;
+    for (video_id, _, _) in cached.iter().take(excess) {
+        for suffix in [".m3u8", ".m3u8.gz", ".original.m3u8"] {
+            let _ = fs::remove_file(cache_dir.join(format!("{}{}", video_id, suffix)));
+        }
+        LAST_SERVED.lock().unwrap().remove(video_id);
+        removed += 1;
+    }
+    if removed < excess {
+        info!(
+            "Manifest cache is {} over its cap, the rest belong to videos in the library",
+            excess - removed
+        );
+    }
+    removed
+}
+
+/// The unfiltered HLS master playlist YouTube serves for a video.
+pub struct RawManifest {
+    // How many formats yt-dlp listed for the video, HLS or not
+    pub format_count: usize,
+    pub content: String,
+}
+
+/// What filtering made of a manifest, returned by the debug endpoint.
+#[derive(Serialize)]
+pub struct ManifestDiagnostics {
+    pub video_id: String,
+    pub ytdlp_formats: usize,
+    // Variant streams in YouTube's manifest, before filtering
+    pub raw_streams: usize,
+    pub selected_bandwidths: Vec<u32>,
+    pub audio_tracks: Vec<String>,
+    pub expires: u64,
+}
+
+pub fn diagnose_manifest(video_id: &str, raw: RawManifest) -> ManifestDiagnostics {
+    let raw_streams = raw
+        .content
+        .lines()
+        .filter(|line| line.starts_with("#EXT-X-STREAM-INF:"))
+        .count();
+    let filtered = filter_and_modify_manifest(raw.content);
+
+    ManifestDiagnostics {
+        video_id: video_id.to_string(),
+        ytdlp_formats: raw.format_count,
+        raw_streams,
+        selected_bandwidths: filtered
+            .lines()
+            .filter(|line| line.starts_with("#EXT-X-STREAM-INF:"))
+            .filter_map(|line| attribute(line, "BANDWIDTH")?.parse().ok())
+            .collect(),
+        audio_tracks: filtered
+            .lines()
+            .filter(|line| line.starts_with("#EXT-X-MEDIA:"))
+            .map(str::to_string)
+            .collect(),
+        expires: ManifestCache::new(video_id, filtered).expires,
+    }
+}
+
+/// How `fetch_and_filter_manifest` stores the manifests it fetches.
+#[derive(Debug, Clone, Copy)]
+pub struct CacheOptions {
+    pub compress: bool,
+    // Also write YouTube's unfiltered manifest to <id>.original.m3u8
+    pub keep_original: bool,
+}
+
+impl CacheOptions {
+    pub fn from_config(config: &Config) -> Self {
+        CacheOptions {
+            compress: config.compress_manifests,
+            keep_original: config.keep_original_manifest,
+        }
     }
 }
 
 pub async fn fetch_and_filter_manifest(
     video_id: &str,
     cache_dir: &Path,
-    save_cache: bool,
+    // None skips the cache, for one-off fetches
+    save_cache: Option<CacheOptions>,
     progress: &ProgressSender,
+    network: &NetworkOptions,
 ) -> Result<String> {
+    let content = fetch_raw_manifest(video_id, progress, network)
+        .await?
+        .content;
+
+    // Save original manifest if requested
+    if let Some(options) = save_cache
+        && options.keep_original
+    {
+        let original_cache = ManifestCache::new(video_id, content.clone());
+        if let Err(e) = original_cache.save_original(cache_dir) {
+            info!("Failed to save original manifest: {}", e);
+        }
+    }
+
+    // Filter and modify the manifest
+    let manifest = filter_and_modify_manifest(content);
+    info!("Renditions of {}: {}", video_id, renditions(&manifest));
+
+    // Ensure manifest ends with newline
+    let manifest = if !manifest.ends_with('\n') {
+        format!("{}\n", manifest)
+    } else {
+        manifest
+    };
+
+    // Nothing is cached, so the next request fetches again instead of serving it
+    if !has_playable_streams(&manifest) {
+        return Err(FetchError::NoStreams.into());
+    }
+
+    // Cache the filtered manifest if requested
+    if let Some(options) = save_cache {
+        let cache = ManifestCache::new(video_id, manifest.clone());
+        if let Err(e) = cache.save(cache_dir, options.compress) {
+            info!("Failed to cache manifest: {}", e);
+        }
+    }
+
+    Ok(manifest)
+}
+
+/// Asks yt-dlp for the video's HLS manifest URL and downloads the manifest as is.
+pub async fn fetch_raw_manifest(
+    video_id: &str,
+    progress: &ProgressSender,
+    network: &NetworkOptions,
+) -> Result<RawManifest> {
     let url = format!("https://www.youtube.com/watch?v={}", video_id);
 
     // Get video metadata as JSON
-    let output = Command::new("yt-dlp")
-        .args(["-j", "--no-playlist", "--cookies", "cookies.txt", &url])
-        .output()
-        .await
-        .map_err(|e| anyhow!("Failed to execute yt-dlp: {}", e))?;
+    let output = network
+        .run_with_cookies(["-j", "--no-playlist", &url])
+        .await?;
 
     // Check if yt-dlp succeeded and output isn't empty
     if !output.status.success() {
         if let Some(progress) = progress {
             let _ = progress
-                .send(format!(
-                    "yt-dlp failed with status {}: {}",
-                    output.status,
-                    String::from_utf8_lossy(&output.stderr)
-                ))
+                .send(SyncProgress::Error {
+                    message: format!(
+                        "yt-dlp failed with status {}: {}",
+                        output.status,
+                        String::from_utf8_lossy(&output.stderr)
+                    ),
+                })
                 .await;
         }
+        if let Some(e) = FetchError::from_stderr(&output.stderr) {
+            return Err(e.into());
+        }
         return Err(anyhow!(
             "yt-dlp failed: {}",
             String::from_utf8_lossy(&output.stderr)
@@ -114,10 +474,7 @@ pub async fn fetch_and_filter_manifest(
         info!("yt-dlp stderr: {}", String::from_utf8_lossy(&output.stderr));
         if let Some(progress) = progress {
             let _ = progress
-                .send(format!(
-                    "yt-dlp stderr: {}",
-                    String::from_utf8_lossy(&output.stderr)
-                ))
+                .send(format!("yt-dlp stderr: {}", String::from_utf8_lossy(&output.stderr)).into())
                 .await;
         }
     }
@@ -144,16 +501,43 @@ pub async fn fetch_and_filter_manifest(
     info!("Found HLS manifest URL: {}", manifest_url);
     if let Some(progress) = progress {
         let _ = progress
-            .send(format!("Found HLS manifest URL: {}", manifest_url))
+            .send(format!("Found HLS manifest URL: {}", manifest_url).into())
             .await;
     }
 
-    let client = Client::new();
-    let content = client
+    let client = network.http_client()?;
+    let content = download_manifest(&client, manifest_url, video_id).await?;
+
+    Ok(RawManifest {
+        format_count: metadata["formats"].as_array().map_or(0, Vec::len),
+        content,
+    })
+}
+
+/// GETs the manifest yt-dlp pointed at, turning non-2xx answers into a `FetchError`
+/// instead of reading an error page as the manifest.
+async fn download_manifest(
+    client: &reqwest::Client,
+    manifest_url: &str,
+    video_id: &str,
+) -> Result<String> {
+    let response = client
         .get(manifest_url)
         .send()
         .await
-        .map_err(|e| anyhow!("Failed to fetch manifest: {}", e))?
+        .map_err(|e| anyhow!("Failed to fetch manifest: {}", e))?;
+
+    let status = response.status();
+    if !status.is_success() {
+        info!("Manifest request for {} returned {}", video_id, status);
+        return Err(if status.as_u16() == 429 {
+            FetchError::RateLimited.into()
+        } else {
+            FetchError::Http(status.as_u16()).into()
+        });
+    }
+
+    let content = response
         .text()
         .await
         .map_err(|e| anyhow!("Failed to read manifest content: {}", e))?;
@@ -162,42 +546,23 @@ pub async fn fetch_and_filter_manifest(
         return Err(anyhow!("Invalid manifest format"));
     }
 
-    // Save original manifest if requested
-    // if save_cache {
-    //     let original_cache = ManifestCache::new(video_id, content.clone());
-    //     if let Err(e) = original_cache.save_original(cache_dir) {
-    //         info!("Failed to save original manifest: {}", e);
-    //     }
-    // }
-
-    // Filter and modify the manifest
-    let manifest = filter_and_modify_manifest(content);
-
-    // Ensure manifest ends with newline
-    let manifest = if !manifest.ends_with('\n') {
-        format!("{}\n", manifest)
-    } else {
-        manifest
-    };
+    Ok(content)
+}
 
-    // Cache the filtered manifest if requested
-    if save_cache {
-        let cache = ManifestCache::new(video_id, manifest.clone());
-        if let Err(e) = cache.save(cache_dir) {
-            info!("Failed to cache manifest: {}", e);
-        }
-    }
+// The audio group YouTube uses for its higher quality audio
+const PREFERRED_AUDIO_GROUP: &str = "234";
 
-    Ok(manifest)
-}
+// Comment carrying `describe_renditions` in filtered manifests, so cached ones are
+// served without working it out again. Players skip lines that aren't #EXT tags
+const RENDITIONS_COMMENT: &str = "#YTSTRM-RENDITIONS:";
 
+/// Keeps the three highest bandwidth video streams and one audio track per
+/// language and group, noting what's left in a `RENDITIONS_COMMENT` line.
 pub fn filter_and_modify_manifest(content: String) -> String {
     let lines: Vec<&str> = content.lines().collect();
     let mut video_streams = Vec::new();
-    let mut high_audio_default = None;
-    let mut high_audio_backup = None;
-    let mut sd_audio_default = None;
-    let mut sd_audio_backup = None;
+    let mut audio_tracks = Vec::new();
+    let mut seen_audio = HashSet::new();
 
     let mut i = 0;
     while i < lines.len() {
@@ -207,68 +572,290 @@ pub fn filter_and_modify_manifest(content: String) -> String {
             let info = line;
             let url = lines[i + 1];
 
-            if let Some(bandwidth_str) = info
-                .split("BANDWIDTH=")
-                .nth(1)
-                .and_then(|s| s.split(',').next())
+            if let Some(bandwidth) =
+                attribute(info, "BANDWIDTH").and_then(|bw| bw.parse::<u32>().ok())
             {
-                if let Ok(bandwidth) = bandwidth_str.parse::<u32>() {
-                    video_streams.push((bandwidth, info, url));
-                }
+                video_streams.push((bandwidth, info, url));
             }
             i += 1; // Skip the URL line
-        } else if line.starts_with("#EXT-X-MEDIA:") && line.contains("URI") {
-            let is_default = line.contains("DEFAULT=YES");
-            if line.contains("234") {
-                if is_default {
-                    high_audio_default = Some(line);
-                } else if high_audio_default.is_none() {
-                    high_audio_backup = Some(line);
-                }
-            } else if is_default {
-                sd_audio_default = Some(line);
-            } else if sd_audio_default.is_none() {
-                sd_audio_backup = Some(line);
+        } else if line.starts_with("#EXT-X-MEDIA:")
+            && attribute(line, "TYPE").as_deref() == Some("AUDIO")
+            && attribute(line, "URI").is_some()
+        {
+            // One track per language in each group, so dubs survive
+            let group = attribute(line, "GROUP-ID");
+            let language = attribute(line, "LANGUAGE");
+            if seen_audio.insert((group.clone(), language)) {
+                audio_tracks.push((group, line));
             }
         }
         i += 1;
     }
 
     // Sort streams by bandwidth (highest to lowest) and take top 3
-    video_streams.sort_by(|a, b| b.0.cmp(&a.0));
+    video_streams.sort_by_key(|stream| std::cmp::Reverse(stream.0));
     video_streams.truncate(3);
 
+    // The high quality group if there is one, else the group of the default track
+    let preferred_group = audio_tracks
+        .iter()
+        .find(|(group, _)| group.as_deref() == Some(PREFERRED_AUDIO_GROUP))
+        .or_else(|| audio_tracks.iter().find(|(_, line)| is_default(line)))
+        .or(audio_tracks.first())
+        .map(|(group, _)| group.clone());
+    // The preferred group's tracks come first
+    audio_tracks.sort_by_key(|(group, _)| Some(group) != preferred_group.as_ref());
+
     // Build final manifest
-    let mut final_manifest = String::from("#EXTM3U\n#EXT-X-INDEPENDENT-SEGMENTS\n");
+    const HEADER: &str = "#EXTM3U\n#EXT-X-INDEPENDENT-SEGMENTS\n";
+    let mut final_manifest = String::from(HEADER);
 
-    // Add audio track (using existing priority order)
-    if let Some(audio) = high_audio_default
-        .or(sd_audio_default)
-        .or(high_audio_backup)
-        .or(sd_audio_backup)
-    {
-        final_manifest.push_str(audio);
+    // Add every audio track, with exactly one default per group
+    let mut defaulted = HashSet::new();
+    let has_default: HashSet<&Option<String>> = audio_tracks
+        .iter()
+        .filter(|(_, line)| is_default(line))
+        .map(|(group, _)| group)
+        .collect();
+    for (group, line) in &audio_tracks {
+        // Keep YouTube's default, or pick the group's first track if it has none
+        let default =
+            (is_default(line) || !has_default.contains(group)) && defaulted.insert(group.clone());
+        let flag = if default { "YES" } else { "NO" };
+        final_manifest.push_str(&with_attribute(line, "DEFAULT", flag));
         final_manifest.push('\n');
     }
 
-    // Add top 3 video streams
+    // Add top 3 video streams, pointed at the preferred audio if their group is gone
     for (_bandwidth, info, url) in video_streams {
-        final_manifest.push_str(info);
+        match (attribute(info, "AUDIO"), &preferred_group) {
+            (Some(group), Some(Some(preferred)))
+                if !audio_tracks.iter().any(|(g, _)| g.as_ref() == Some(&group)) =>
+            {
+                let quoted = format!("\"{}\"", preferred);
+                final_manifest.push_str(&with_attribute(info, "AUDIO", &quoted));
+            }
+            _ => final_manifest.push_str(info),
+        }
         final_manifest.push('\n');
         final_manifest.push_str(url);
         final_manifest.push('\n');
     }
 
+    let renditions = format!(
+        "{}{}\n",
+        RENDITIONS_COMMENT,
+        describe_renditions(&final_manifest)
+    );
+    final_manifest.insert_str(HEADER.len(), &renditions);
     final_manifest
 }
 
+/// Whether `manifest` has at least one video stream and some audio, either as an
+/// audio track or muxed into a stream.
+fn has_playable_streams(manifest: &str) -> bool {
+    let streams: Vec<&str> = manifest
+        .lines()
+        .filter(|line| line.starts_with("#EXT-X-STREAM-INF:"))
+        .collect();
+    let audio_track = manifest.lines().any(|line| {
+        line.starts_with("#EXT-X-MEDIA:") && attribute(line, "TYPE").as_deref() == Some("AUDIO")
+    });
+    let muxed_audio = streams
+        .iter()
+        .any(|line| attribute(line, "CODECS").is_some_and(|codecs| codecs.contains("mp4a")));
+    !streams.is_empty() && (audio_track || muxed_audio)
+}
+
+fn is_default(line: &str) -> bool {
+    attribute(line, "DEFAULT").as_deref() == Some("YES")
+}
+
+/// Splits an #EXT-X tag line into its tag and attributes, with values exactly as
+/// written (quotes included). Quoted values may contain commas, e.g.
+/// `CODECS="avc1.640028,mp4a.40.2"`.
+fn attribute_list(line: &str) -> Option<(&str, Vec<(&str, &str)>)> {
+    let (tag, mut rest) = line.split_once(':')?;
+    let mut attributes = Vec::new();
+    while let Some((key, after)) = rest.split_once('=') {
+        let end = match after.strip_prefix('"') {
+            Some(quoted) => quoted.find('"')? + 2,
+            None => after.find(',').unwrap_or(after.len()),
+        };
+        let (value, next) = after.split_at(end);
+        attributes.push((key, value));
+        rest = next.strip_prefix(',').unwrap_or(next);
+    }
+    Some((tag, attributes))
+}
+
+/// The value of attribute `name` on an #EXT-X tag line, without quotes.
+fn attribute(line: &str, name: &str) -> Option<String> {
+    attribute_list(line)?
+        .1
+        .into_iter()
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value.trim_matches('"').to_string())
+}
+
+/// `line` with attribute `name` set to `value` (quoted by the caller if it needs
+/// to be), appended if the line didn't have it.
+fn with_attribute(line: &str, name: &str, value: &str) -> String {
+    let Some((tag, mut attributes)) = attribute_list(line) else {
+        return line.to_string();
+    };
+    match attributes.iter_mut().find(|(key, _)| *key == name) {
+        Some(attribute) => attribute.1 = value,
+        None => attributes.push((name, value)),
+    }
+    let attributes: Vec<String> = attributes
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect();
+    format!("{}:{}", tag, attributes.join(","))
+}
+
+/// A filtered manifest's streams, served as `/stream/{id}/info.json`.
+#[derive(Serialize)]
+pub struct ManifestInfo {
+    pub video_id: String,
+    // Highest bandwidth first, as in the manifest
+    pub renditions: Vec<Rendition>,
+    pub audio_tracks: Vec<AudioTrack>,
+    // Unix seconds, after which the URIs stop working
+    pub expires: u64,
+}
+
+#[derive(Serialize)]
+pub struct Rendition {
+    pub resolution: Option<String>,
+    pub height: Option<u32>,
+    pub bandwidth: Option<u32>,
+    pub codecs: Option<String>,
+    pub frame_rate: Option<f32>,
+    // GROUP-ID of the audio track it plays with
+    pub audio_group: Option<String>,
+    // The rendition's own media playlist
+    pub uri: String,
+}
+
+#[derive(Serialize)]
+pub struct AudioTrack {
+    pub group_id: Option<String>,
+    pub name: Option<String>,
+    pub language: Option<String>,
+    pub default: bool,
+    pub uri: Option<String>,
+}
+
+/// Describes a manifest produced by `filter_and_modify_manifest`, so the JSON lists
+/// exactly the streams the m3u8 from `/stream/{id}` offers.
+pub fn manifest_info(video_id: &str, manifest: &str) -> ManifestInfo {
+    let lines: Vec<&str> = manifest.lines().collect();
+    let renditions = lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| line.starts_with("#EXT-X-STREAM-INF:"))
+        .map(|(i, line)| {
+            let resolution = attribute(line, "RESOLUTION");
+            Rendition {
+                height: resolution
+                    .as_deref()
+                    .and_then(|res| res.split('x').nth(1)?.parse().ok()),
+                resolution,
+                bandwidth: attribute(line, "BANDWIDTH").and_then(|bw| bw.parse().ok()),
+                codecs: attribute(line, "CODECS"),
+                frame_rate: attribute(line, "FRAME-RATE").and_then(|rate| rate.parse().ok()),
+                audio_group: attribute(line, "AUDIO"),
+                uri: lines.get(i + 1).unwrap_or(&"").to_string(),
+            }
+        })
+        .collect();
+    let audio_tracks = lines
+        .iter()
+        .filter(|line| line.starts_with("#EXT-X-MEDIA:"))
+        .map(|line| AudioTrack {
+            group_id: attribute(line, "GROUP-ID"),
+            name: attribute(line, "NAME"),
+            language: attribute(line, "LANGUAGE"),
+            default: attribute(line, "DEFAULT").as_deref() == Some("YES"),
+            uri: attribute(line, "URI"),
+        })
+        .collect();
+
+    ManifestInfo {
+        video_id: video_id.to_string(),
+        renditions,
+        audio_tracks,
+        expires: ManifestCache::new(video_id, manifest.to_string()).expires,
+    }
+}
+
+/// The renditions `filter_and_modify_manifest` left in `manifest`, for the
+/// X-Ytstrm-Renditions header, e.g. `1080p,720p,480p; audio=234`. Read from its
+/// comment, worked out again only for manifests cached before it was written.
+pub fn renditions(manifest: &str) -> String {
+    manifest
+        .lines()
+        .find_map(|line| line.strip_prefix(RENDITIONS_COMMENT))
+        .map_or_else(|| describe_renditions(manifest), str::to_string)
+}
+
+/// Summarizes the video streams and first audio group of a filtered manifest.
+fn describe_renditions(manifest: &str) -> String {
+    let videos: Vec<String> = manifest
+        .lines()
+        .filter(|line| line.starts_with("#EXT-X-STREAM-INF:"))
+        .filter_map(|line| {
+            // Prefer the height, fall back to bandwidth when there's no resolution
+            attribute(line, "RESOLUTION")
+                .and_then(|res| res.split('x').nth(1).map(|h| format!("{}p", h)))
+                .or_else(|| attribute(line, "BANDWIDTH").map(|bw| format!("{}bps", bw)))
+        })
+        .collect();
+
+    let audio = manifest
+        .lines()
+        .find(|line| line.starts_with("#EXT-X-MEDIA:"))
+        .and_then(|line| attribute(line, "GROUP-ID"))
+        .unwrap_or_else(|| "none".to_string());
+
+    format!("{}; audio={}", videos.join(","), audio)
+}
+
 #[derive(Clone)]
 struct ManifestMaintenanceInfo {
     jellyfin_media_path: PathBuf,
+    max_manifest_cache_age_secs: u64,
+    interval: Duration,
+    refresh_lead_secs: u64,
+    refresh_delay: Duration,
+    cache_options: CacheOptions,
+    network: NetworkOptions,
+    max_cached_manifests: Option<usize>,
+    // Eviction still runs with refreshes turned off
+    refresh_enabled: bool,
+    // Only needed to tell which manifests eviction must keep
+    channels: Vec<Channel>,
+}
+
+/// The cached manifests maintenance should fetch again: those expiring within
+/// `refresh_lead_secs` or older than `max_age_secs`.
+fn due_for_refresh(
+    cached: &[ManifestCache],
+    refresh_lead_secs: u64,
+    max_age_secs: u64,
+) -> Vec<&str> {
+    let refresh_before = now_secs() + refresh_lead_secs;
+    cached
+        .iter()
+        .filter(|cache| cache.expires < refresh_before || cache.is_too_old(max_age_secs))
+        .map(|cache| cache.video_id.as_str())
+        .collect()
 }
 
-pub async fn maintain_manifest_cache(config: ConfigState) {
-    loop {
+pub async fn maintain_manifest_cache(config: ConfigState, shutdown: CancellationToken) {
+    while !shutdown.is_cancelled() {
         // Get config info with minimal lock time
         let maintenance_info = {
             let config_guard = config.read().await;
@@ -277,19 +864,21 @@ pub async fn maintain_manifest_cache(config: ConfigState) {
             if config_guard.channels.is_empty() {
                 info!("No channels configured, skipping manifest maintenance");
                 drop(config_guard);
-                tokio::time::sleep(tokio::time::Duration::from_secs(900)).await;
-                continue;
-            }
-
-            if config_guard.maintain_manifest_cache == false {
-                info!("Manifest maintenance is disabled, skipping");
-                drop(config_guard);
-                tokio::time::sleep(tokio::time::Duration::from_secs(900)).await;
+                sleep_or_shutdown(Duration::from_secs(900), &shutdown).await;
                 continue;
             }
 
             ManifestMaintenanceInfo {
+                refresh_enabled: config_guard.maintain_manifest_cache,
                 jellyfin_media_path: config_guard.jellyfin_media_path.clone(),
+                max_manifest_cache_age_secs: config_guard.max_manifest_cache_age_secs,
+                interval: Duration::from_secs(config_guard.manifest_maintenance_interval_secs),
+                refresh_lead_secs: config_guard.manifest_refresh_lead_secs,
+                refresh_delay: Duration::from_secs(config_guard.manifest_refresh_delay_secs),
+                cache_options: CacheOptions::from_config(&config_guard),
+                network: NetworkOptions::from_config(&config_guard),
+                max_cached_manifests: config_guard.max_cached_manifests,
+                channels: config_guard.channels.clone(),
             }
         };
 
@@ -302,48 +891,426 @@ pub async fn maintain_manifest_cache(config: ConfigState) {
         }
 
         let ignore_file = cache_dir.join(".ignore");
-        if !ignore_file.exists() {
-            if let Err(e) = fs::write(&ignore_file, "") {
-                info!("Failed to create .ignore file: {}", e);
-            }
+        if !ignore_file.exists()
+            && let Err(e) = fs::write(&ignore_file, "")
+        {
+            info!("Failed to create .ignore file: {}", e);
         }
 
-        if let Ok(files) = fs::read_dir(&cache_dir) {
+        if !maintenance_info.refresh_enabled {
+            info!("Manifest refresh is disabled, skipping");
+        } else {
+            let cached = list_cached(&cache_dir);
             let mut count = 0;
-            let mut files_count = 0;
-            for file in files.flatten() {
-                if let Some(file_name) = file.file_name().to_str() {
-                    if !file_name.ends_with(".m3u8") {
-                        continue;
+            for video_id in due_for_refresh(
+                &cached,
+                maintenance_info.refresh_lead_secs,
+                maintenance_info.max_manifest_cache_age_secs,
+            ) {
+                if shutdown.is_cancelled() {
+                    break;
+                }
+                info!("Refreshing manifest for {}", video_id);
+                count += 1;
+                match fetch_and_filter_manifest(
+                    video_id,
+                    &cache_dir,
+                    Some(maintenance_info.cache_options),
+                    &None,
+                    &maintenance_info.network,
+                )
+                .await
+                {
+                    Ok(_) => metrics().manifest_refreshed(),
+                    Err(e)
+                        if matches!(
+                            e.downcast_ref::<FetchError>(),
+                            Some(FetchError::RateLimited)
+                        ) =>
+                    {
+                        // The rest would be refused too, try again next round
+                        info!("Rate limited, pausing manifest refreshes");
+                        break;
                     }
-
-                    let video_id = file_name.trim_end_matches(".m3u8");
-                    if let Ok(cache) = ManifestCache::load(video_id, &cache_dir) {
-                        files_count += 1;
-                        let now = SystemTime::now()
-                            .duration_since(UNIX_EPOCH)
-                            .unwrap()
-                            .as_secs();
-
-                        if cache.expires < (now + 1800) {
-                            info!("Refreshing manifest for {}", video_id);
-                            count += 1;
-                            if let Err(e) =
-                                fetch_and_filter_manifest(video_id, &cache_dir, true, &None).await
-                            {
-                                info!("Failed to refresh manifest for {}: {}", video_id, e);
-                            }
-                            tokio::time::sleep(Duration::from_secs(15)).await;
-                        }
+                    Err(e) => {
+                        info!("Failed to refresh manifest for {}: {}", video_id, e)
                     }
                 }
+                sleep_or_shutdown(maintenance_info.refresh_delay, &shutdown).await;
             }
             info!(
                 "Checked {} manifest files, refreshed {} expired manifests",
-                files_count, count
+                cached.len(),
+                count
             );
         }
 
-        tokio::time::sleep(tokio::time::Duration::from_secs(1800)).await;
+        if let Some(max) = maintenance_info.max_cached_manifests {
+            let library = materialized_video_ids(&maintenance_info.channels);
+            let evicted = evict_manifests(&cache_dir, max, &library);
+            if evicted > 0 {
+                info!(
+                    "Evicted {} least recently used manifests to stay under {}",
+                    evicted, max
+                );
+            }
+        }
+
+        sleep_or_shutdown(maintenance_info.interval, &shutdown).await;
+    }
+
+    info!("Manifest maintenance stopped");
+}
+
+/// Caches the manifest of every episode of `channel` that has none or whose cached
+/// one is due for a refresh, reporting each to `progress`. Fetches are spaced by
+/// `manifest_refresh_delay_secs` like maintenance's. Stops when YouTube rate limits,
+/// on shutdown, or once nobody listens to `progress`. Returns how many were cached.
+pub async fn precache_channel_manifests(
+    channel: &Channel,
+    settings: &Config,
+    progress: mpsc::Sender<SyncProgress>,
+    shutdown: &CancellationToken,
+) -> Result<usize> {
+    let cache_dir = settings.jellyfin_media_path.join("manifests");
+    let cache_options = CacheOptions::from_config(settings);
+    let network = NetworkOptions::from_config(settings);
+    let delay = Duration::from_secs(settings.manifest_refresh_delay_secs);
+
+    // Video id and title, the id standing in for episodes without a sidecar
+    let episodes: Vec<(String, String)> = strm_files(&channel.media_dir)
+        .iter()
+        .filter_map(|path| {
+            let content = fs::read_to_string(path).ok()?;
+            let video_id = strm_video_id(&content)?.to_string();
+            let title = VideoInfo::load_sidecar(path)
+                .map_or_else(|_| video_id.clone(), |video| video.title);
+            Some((video_id, title))
+        })
+        .collect();
+    let total = episodes.len();
+    let sender = Some(progress.clone());
+
+    let mut cached = 0;
+    for (i, (video_id, title)) in episodes.into_iter().enumerate() {
+        if shutdown.is_cancelled() || progress.is_closed() {
+            break;
+        }
+        let _ = progress
+            .send(SyncProgress::Progress {
+                current: i + 1,
+                total: Some(total),
+                video_title: title.clone(),
+            })
+            .await;
+
+        let fresh = ManifestCache::load(&video_id, &cache_dir).is_ok_and(|cache| {
+            cache.expires >= now_secs() + settings.manifest_refresh_lead_secs
+                && !cache.is_too_old(settings.max_manifest_cache_age_secs)
+        });
+        if fresh {
+            continue;
+        }
+
+        let fetched = fetch_and_filter_manifest(
+            &video_id,
+            &cache_dir,
+            Some(cache_options),
+            &sender,
+            &network,
+        )
+        .await;
+        match fetched {
+            Ok(_) => {
+                cached += 1;
+                metrics().manifest_refreshed();
+                let _ = progress
+                    .send(format!("Cached manifest for {}\n", title).into())
+                    .await;
+            }
+            Err(e)
+                if matches!(
+                    e.downcast_ref::<FetchError>(),
+                    Some(FetchError::RateLimited)
+                ) =>
+            {
+                return Err(e.context(format!("Stopped after caching {} manifests", cached)));
+            }
+            Err(e) => {
+                let _ = progress
+                    .send(SyncProgress::Error {
+                        message: format!("Failed to cache manifest for {}: {}\n", title, e),
+                    })
+                    .await;
+            }
+        }
+        sleep_or_shutdown(delay, shutdown).await;
+    }
+
+    let message = format!("Cached {} of {} manifests\n", cached, total);
+    info!("{}: {}", channel.get_name(), message.trim_end());
+    let _ = progress.send(message.into()).await;
+    Ok(cached)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Trimmed down from a real YouTube master playlist
+    const MANIFEST: &str = r#"#EXTM3U
+#EXT-X-INDEPENDENT-SEGMENTS
+#EXT-X-MEDIA:URI="https://manifest.googlevideo.com/a/233",TYPE=AUDIO,GROUP-ID="233",LANGUAGE="en",NAME="English",DEFAULT=YES,AUTOSELECT=YES
+#EXT-X-MEDIA:URI="https://manifest.googlevideo.com/a/234",TYPE=AUDIO,GROUP-ID="234",LANGUAGE="en",NAME="English",DEFAULT=YES,AUTOSELECT=YES
+#EXT-X-STREAM-INF:BANDWIDTH=500000,CODECS="avc1.4d401e",RESOLUTION=640x360,FRAME-RATE=30,AUDIO="233"
+https://manifest.googlevideo.com/v/360/expire/1900000000/
+#EXT-X-STREAM-INF:BANDWIDTH=5000000,CODECS="avc1.640028",RESOLUTION=1920x1080,FRAME-RATE=30,AUDIO="234"
+https://manifest.googlevideo.com/v/1080/expire/1900000000/
+#EXT-X-STREAM-INF:BANDWIDTH=1000000,CODECS="avc1.4d401f",RESOLUTION=854x480,FRAME-RATE=30,AUDIO="233"
+https://manifest.googlevideo.com/v/480/expire/1900000000/
+#EXT-X-STREAM-INF:BANDWIDTH=2500000,CODECS="avc1.4d401f",RESOLUTION=1280x720,FRAME-RATE=30,AUDIO="234"
+https://manifest.googlevideo.com/v/720/expire/1900000000/
+"#;
+
+    #[test]
+    fn renditions_header_matches_the_filtered_manifest() {
+        let filtered = filter_and_modify_manifest(MANIFEST.to_string());
+        let lines: Vec<&str> = filtered.lines().collect();
+        assert_eq!(lines[0], "#EXTM3U");
+        assert_eq!(lines[2], "#YTSTRM-RENDITIONS:1080p,720p,480p; audio=234");
+        assert_eq!(renditions(&filtered), "1080p,720p,480p; audio=234");
+
+        // Manifests cached before the comment existed are described from their streams
+        let uncommented: String = filtered
+            .lines()
+            .filter(|line| !line.starts_with(RENDITIONS_COMMENT))
+            .map(|line| format!("{}\n", line))
+            .collect();
+        assert_eq!(renditions(&uncommented), "1080p,720p,480p; audio=234");
+    }
+
+    #[test]
+    fn old_manifests_expire_whatever_their_expiry_token_says() {
+        let dir = tempfile::tempdir().unwrap();
+        let content = "#EXTM3U\nhttps://manifest.googlevideo.com/v/expire/99999999999/\n";
+        let cache = ManifestCache::new("abc", content.to_string());
+        assert_eq!(cache.expires, 99999999999);
+        cache.save(dir.path(), false).unwrap();
+
+        let two_days_ago = SystemTime::now() - Duration::from_secs(2 * 24 * 60 * 60);
+        fs::File::options()
+            .write(true)
+            .open(dir.path().join("abc.m3u8"))
+            .unwrap()
+            .set_modified(two_days_ago)
+            .unwrap();
+
+        let loaded = ManifestCache::load("abc", dir.path()).unwrap();
+        assert!(loaded.is_too_old(24 * 60 * 60));
+        assert!(!loaded.is_valid(24 * 60 * 60));
+        assert!(loaded.is_valid(3 * 24 * 60 * 60));
+    }
+
+    #[test]
+    fn dubbed_audio_tracks_survive_filtering() {
+        let dubbed = r#"#EXTM3U
+#EXT-X-MEDIA:URI="https://manifest.googlevideo.com/a/234/en",TYPE=AUDIO,GROUP-ID="234",LANGUAGE="en",NAME="English",DEFAULT=YES,AUTOSELECT=YES
+#EXT-X-MEDIA:URI="https://manifest.googlevideo.com/a/234/en2",TYPE=AUDIO,GROUP-ID="234",LANGUAGE="en",NAME="English",DEFAULT=NO,AUTOSELECT=YES
+#EXT-X-MEDIA:URI="https://manifest.googlevideo.com/a/234/es",TYPE=AUDIO,GROUP-ID="234",LANGUAGE="es",NAME="Español",DEFAULT=NO,AUTOSELECT=YES
+#EXT-X-STREAM-INF:BANDWIDTH=5000000,CODECS="avc1.640028",RESOLUTION=1920x1080,AUDIO="234"
+https://manifest.googlevideo.com/v/1080/expire/1900000000/
+"#;
+        let filtered = filter_and_modify_manifest(dubbed.to_string());
+        let info = manifest_info("abc", &filtered);
+
+        let languages: Vec<Option<&str>> = info
+            .audio_tracks
+            .iter()
+            .map(|track| track.language.as_deref())
+            .collect();
+        assert_eq!(languages, [Some("en"), Some("es")]);
+        // Only one is played by default
+        let defaults: Vec<bool> = info
+            .audio_tracks
+            .iter()
+            .map(|track| track.default)
+            .collect();
+        assert_eq!(defaults, [true, false]);
+        // Every stream plays with a group that is still there
+        for rendition in &info.renditions {
+            assert!(
+                info.audio_tracks
+                    .iter()
+                    .any(|track| track.group_id == rendition.audio_group)
+            );
+        }
+    }
+
+    #[test]
+    fn geo_block_stderr_maps_to_the_blocked_status() {
+        let stderr = b"WARNING: [youtube] abc: Falling back\nERROR: [youtube] abc: The uploader has not made this video available in your country\n";
+        assert!(matches!(
+            FetchError::from_stderr(stderr),
+            Some(FetchError::GeoBlocked)
+        ));
+        assert!(matches!(
+            FetchError::from_stderr(b"ERROR: [youtube] abc: Video unavailable. This video is not available in your country"),
+            Some(FetchError::GeoBlocked)
+        ));
+        assert!(FetchError::from_stderr(b"ERROR: Unable to download webpage: timed out").is_none());
+
+        let scan = b"ERROR: [youtube] abc: The uploader has not made this video available in your country\nERROR: [youtube] def: Private video\nERROR: [youtube] ghi: This video is geo-restricted\n";
+        assert_eq!(crate::ytdlp::geo_blocked_ids(scan), ["abc", "ghi"]);
+    }
+
+    #[test]
+    fn compressed_manifests_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        ManifestCache::new("abc", MANIFEST.to_string())
+            .save(dir.path(), true)
+            .unwrap();
+        assert!(dir.path().join("abc.m3u8.gz").exists());
+        assert!(!dir.path().join("abc.m3u8").exists());
+
+        let loaded = ManifestCache::load("abc", dir.path()).unwrap();
+        assert_eq!(loaded.content, MANIFEST);
+        assert_eq!(loaded.expires, 1900000000);
+        // Served as is to clients that accept gzip
+        assert_eq!(gunzip(&loaded.gzipped.unwrap()).unwrap(), MANIFEST);
+
+        // Turning compression off replaces the .gz rather than hiding behind it
+        ManifestCache::new("abc", "#EXTM3U\n".to_string())
+            .save(dir.path(), false)
+            .unwrap();
+        assert!(!dir.path().join("abc.m3u8.gz").exists());
+        let loaded = ManifestCache::load("abc", dir.path()).unwrap();
+        assert_eq!(loaded.content, "#EXTM3U\n");
+        assert!(loaded.gzipped.is_none());
+    }
+
+    #[test]
+    fn only_canonical_manifest_files_are_refreshed() {
+        let dir = tempfile::tempdir().unwrap();
+        let expired = "#EXTM3U\nhttps://manifest.googlevideo.com/v/expire/1000/\n";
+        for id in ["abc", "def"] {
+            let cache = ManifestCache::new(id, expired.to_string());
+            cache.save(dir.path(), id == "def").unwrap();
+            cache.save_original(dir.path()).unwrap();
+        }
+        // Sidecars whose video has no cached manifest of its own
+        ManifestCache::new("ghi", expired.to_string())
+            .save_original(dir.path())
+            .unwrap();
+        fs::write(dir.path().join("jkl.meta"), "{}").unwrap();
+        fs::write(dir.path().join("mno.m3u8.tmp"), expired).unwrap();
+        // Good for hours yet
+        ManifestCache::new("pqr", MANIFEST.to_string())
+            .save(dir.path(), false)
+            .unwrap();
+
+        let cached = list_cached(dir.path());
+        assert_eq!(cached.len(), 3);
+        assert_eq!(
+            due_for_refresh(&cached, 60 * 60, 24 * 60 * 60),
+            ["abc", "def"]
+        );
+    }
+
+    #[test]
+    fn cached_manifests_are_listed_and_cleared_with_their_sidecars() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache_ids = |dir: &Path| -> Vec<String> {
+            list_cached(dir)
+                .into_iter()
+                .map(|cache| cache.video_id)
+                .collect()
+        };
+
+        let abc = ManifestCache::new("abc", MANIFEST.to_string());
+        abc.save(dir.path(), false).unwrap();
+        abc.save_original(dir.path()).unwrap();
+        ManifestCache::new("def", MANIFEST.to_string())
+            .save(dir.path(), true)
+            .unwrap();
+        fs::write(dir.path().join("notes.txt"), "not a manifest").unwrap();
+
+        assert_eq!(cache_ids(dir.path()), ["abc", "def"]);
+        let listed = &list_cached(dir.path())[0];
+        assert_eq!(listed.expires, 1900000000);
+
+        // One video takes its original along with it
+        assert_eq!(clear_cached(dir.path(), Some("abc")).unwrap(), 2);
+        assert!(!dir.path().join("abc.original.m3u8").exists());
+        assert_eq!(cache_ids(dir.path()), ["def"]);
+
+        assert_eq!(clear_cached(dir.path(), None).unwrap(), 1);
+        assert!(cache_ids(dir.path()).is_empty());
+        assert!(dir.path().join("notes.txt").exists());
+
+        assert_eq!(clear_cached(&dir.path().join("missing"), None).unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn manifest_error_statuses_become_fetch_errors() {
+        use axum::{Router, http::StatusCode, routing::get};
+
+        let app = Router::new()
+            .route(
+                "/forbidden",
+                get(|| async { (StatusCode::FORBIDDEN, "#EXTM3U") }),
+            )
+            .route("/busy", get(|| async { StatusCode::TOO_MANY_REQUESTS }))
+            .route("/ok", get(|| async { MANIFEST }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        let client = reqwest::Client::new();
+
+        // The body looks like a manifest, but the status says it isn't one
+        let error = download_manifest(&client, &format!("{}/forbidden", base), "abc")
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<FetchError>(),
+            Some(FetchError::Http(403))
+        ));
+
+        let error = download_manifest(&client, &format!("{}/busy", base), "abc")
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<FetchError>(),
+            Some(FetchError::RateLimited)
+        ));
+
+        let content = download_manifest(&client, &format!("{}/ok", base), "abc")
+            .await
+            .unwrap();
+        assert_eq!(content, MANIFEST);
+    }
+
+    #[test]
+    fn manifests_that_filter_down_to_nothing_are_not_playable() {
+        // No stream has a bandwidth and the audio has no URI, so nothing survives
+        let unusual = "#EXTM3U
+#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"234\",NAME=\"English\"
+#EXT-X-STREAM-INF:RESOLUTION=1920x1080,AUDIO=\"234\"
+https://manifest.googlevideo.com/v/1080/
+";
+        let filtered = filter_and_modify_manifest(unusual.to_string());
+        assert!(!filtered.contains("#EXT-X-STREAM-INF"));
+        assert!(!has_playable_streams(&filtered));
+
+        // Video without any audio to play it with
+        let silent: String = filter_and_modify_manifest(MANIFEST.to_string())
+            .lines()
+            .filter(|line| !line.starts_with("#EXT-X-MEDIA:"))
+            .map(|line| format!("{}\n", line))
+            .collect();
+        assert!(!has_playable_streams(&silent));
+
+        assert!(has_playable_streams(&filter_and_modify_manifest(
+            MANIFEST.to_string()
+        )));
     }
 }
@@ -0,0 +1,115 @@
+use axum::extract::State;
+use axum::response::{IntoResponse, Response};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{LazyLock, Mutex};
+
+use crate::AppStateArc;
+
+/// Process-wide counters, rendered in the Prometheus text format by `/metrics`.
+#[derive(Default)]
+pub struct Metrics {
+    videos_processed: Mutex<BTreeMap<String, u64>>,
+    manifest_cache_hits: AtomicU64,
+    manifest_cache_misses: AtomicU64,
+    manifest_refreshes: AtomicU64,
+}
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::default);
+
+pub fn metrics() -> &'static Metrics {
+    &METRICS
+}
+
+impl Metrics {
+    pub fn video_processed(&self, channel: &str) {
+        let mut videos = self.videos_processed.lock().unwrap();
+        *videos.entry(channel.to_string()).or_default() += 1;
+    }
+
+    pub fn manifest_cache_hit(&self) {
+        self.manifest_cache_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn manifest_cache_miss(&self) {
+        self.manifest_cache_misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn manifest_refreshed(&self) {
+        self.manifest_refreshes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn render(&self, channels: usize, stale_channels: usize) -> String {
+        let mut out = String::new();
+
+        out.push_str("# HELP ytstrm_videos_processed_total Videos added to the library.\n");
+        out.push_str("# TYPE ytstrm_videos_processed_total counter\n");
+        for (channel, count) in self.videos_processed.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "ytstrm_videos_processed_total{{channel=\"{}\"}} {}",
+                escape_label(channel),
+                count
+            );
+        }
+
+        let counters = [
+            (
+                "ytstrm_manifest_cache_hits_total",
+                "Streams served from a cached manifest.",
+                &self.manifest_cache_hits,
+            ),
+            (
+                "ytstrm_manifest_cache_misses_total",
+                "Streams that had to fetch a fresh manifest.",
+                &self.manifest_cache_misses,
+            ),
+            (
+                "ytstrm_manifest_refreshes_total",
+                "Cached manifests refreshed by the maintenance loop.",
+                &self.manifest_refreshes,
+            ),
+        ];
+        for (name, help, value) in counters {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} counter", name);
+            let _ = writeln!(out, "{} {}", name, value.load(Ordering::Relaxed));
+        }
+
+        out.push_str("# HELP ytstrm_channels Configured channels and playlists.\n");
+        out.push_str("# TYPE ytstrm_channels gauge\n");
+        let _ = writeln!(out, "ytstrm_channels {}", channels);
+
+        out.push_str(
+            "# HELP ytstrm_stale_channels Channels not checked for several check intervals.\n",
+        );
+        out.push_str("# TYPE ytstrm_stale_channels gauge\n");
+        let _ = writeln!(out, "ytstrm_stale_channels {}", stale_channels);
+
+        out
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+pub async fn metrics_handler(State(state): State<AppStateArc>) -> Response {
+    let config = state.config.read().await;
+    let channels = config.channels.len();
+    let stale_channels = config
+        .channels
+        .iter()
+        .filter(|channel| channel.is_stale(&config))
+        .count();
+    drop(config);
+    (
+        [("Content-Type", "text/plain; version=0.0.4")],
+        metrics().render(channels, stale_channels),
+    )
+        .into_response()
+}
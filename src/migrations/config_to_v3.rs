@@ -0,0 +1,8 @@
+use anyhow::Result;
+use serde_json::Value;
+
+/// v3 added the switch that pauses background tasks, off for existing setups.
+pub fn migrate(mut config: Value) -> Result<Value> {
+    config["background_tasks_paused"] = false.into();
+    Ok(config)
+}
@@ -0,0 +1,8 @@
+use anyhow::Result;
+use serde_json::Value;
+
+/// v4 added manifest maintenance, off for existing setups.
+pub fn migrate(mut config: Value) -> Result<Value> {
+    config["maintain_manifest_cache"] = false.into();
+    Ok(config)
+}
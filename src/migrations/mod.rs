@@ -1,15 +1,143 @@
-use anyhow::Result;
-use std::path::PathBuf;
+use anyhow::{Result, anyhow};
+use serde_json::Value;
+use tracing::{info, warn};
+
+use crate::config::Config;
 
 mod config_to_v2;
+mod config_to_v3;
+mod config_to_v4;
+
+/// The `version` written to config.json. Bump it together with a new step in
+/// `MIGRATIONS`; fields added with a serde default don't need one.
+pub const CONFIG_VERSION: u32 = 4;
+
+/// Each step turns a config of the given version into the next one. Steps work on
+/// the raw JSON so older files never need a struct of their own.
+type Migration = fn(Value) -> Result<Value>;
+const MIGRATIONS: [(u32, Migration); 3] = [
+    (1, config_to_v2::migrate),
+    (2, config_to_v3::migrate),
+    (3, config_to_v4::migrate),
+];
 
-pub fn run_migrations() -> Result<()> {
-    let config_path = dirs::config_dir()
-        .unwrap_or_else(|| PathBuf::from("/etc"))
-        .join("ytstrm");
-    if config_path.exists() {
-        config_to_v2::migrate_config(&config_path)?;
+pub async fn run_migrations() -> Result<()> {
+    let config_path = Config::config_dir().join("config.json");
+    if !config_path.exists() {
+        return Ok(());
     }
 
+    let content = std::fs::read_to_string(&config_path)?;
+    let (config, migrated) = match migrate_config(&content) {
+        Ok(result) => result,
+        Err(e) => {
+            // Kept as it was, in case fixing config.json by hand goes wrong
+            let backup_path = config_path.with_file_name(format!(
+                "config.json.bak-{}",
+                chrono::Local::now().format("%Y%m%d%H%M%S")
+            ));
+            std::fs::copy(&config_path, &backup_path)
+                .map_err(|copy_error| anyhow!("{:#} (backing it up failed: {})", e, copy_error))?;
+            return Err(anyhow!("{:#} (a copy is at {})", e, backup_path.display()));
+        }
+    };
+    if migrated {
+        config.save().await?;
+    } else {
+        info!("Config is already in proper format");
+    }
     Ok(())
 }
+
+/// Parses an imported config.json, migrating older formats like startup does.
+pub fn parse_config(content: &str) -> Result<Config> {
+    migrate_config(content).map(|(config, _)| config)
+}
+
+/// Parses config.json content of any version, applying the migrations from its
+/// version up to `CONFIG_VERSION`. Returns the config and whether it changed.
+fn migrate_config(content: &str) -> Result<(Config, bool)> {
+    let mut value: Value =
+        serde_json::from_str(content).map_err(|e| anyhow!("Not valid JSON: {}", e))?;
+    let declared = value["version"]
+        .as_u64()
+        .filter(|version| *version > 0)
+        .map(|version| version as u32);
+    let from = declared.unwrap_or_else(|| legacy_version(&value));
+    let tried = match declared {
+        Some(_) => format!("read as v{}, as it declares", from),
+        None => format!("read as v{}, guessed from its fields", from),
+    };
+
+    if from > CONFIG_VERSION {
+        // Unknown fields are ignored, so it is read as well as this version can
+        warn!(
+            "Config is version {}, newer than this ytstrm understands ({})",
+            from, CONFIG_VERSION
+        );
+        return Ok((parse_current(value, &tried)?, false));
+    }
+
+    for (version, migrate) in MIGRATIONS {
+        if version >= from {
+            info!("Migrating config from v{} to v{}", version, version + 1);
+            value = migrate(value).map_err(|e| {
+                anyhow!(
+                    "Not a known config format ({}, then migrating to v{} failed): {}",
+                    tried,
+                    version + 1,
+                    e
+                )
+            })?;
+        }
+    }
+    value["version"] = CONFIG_VERSION.into();
+    let tried = if from < CONFIG_VERSION {
+        format!("{}, then migrated to v{}", tried, CONFIG_VERSION)
+    } else {
+        tried
+    };
+    let config = parse_current(value, &tried)?;
+    // Files from before `version` existed are rewritten to record it
+    Ok((config, declared != Some(CONFIG_VERSION)))
+}
+
+/// `tried` says how the file was read, for the error when it still doesn't fit.
+fn parse_current(value: Value, tried: &str) -> Result<Config> {
+    serde_json::from_value(value)
+        .map_err(|e| anyhow!("Not a known config format ({}): {}", tried, e))
+}
+
+/// The version of a config written before `version` was recorded, from its shape.
+fn legacy_version(value: &Value) -> u32 {
+    let has = |field: &str| value.get(field).is_some();
+    let legacy_channels = value["channels"]
+        .as_array()
+        .is_some_and(|channels| channels.iter().any(|c| c.get("source").is_none()));
+    if has("maintain_manifest_cache") {
+        4
+    } else if has("background_tasks_paused") {
+        3
+    } else if legacy_channels {
+        1
+    } else {
+        2
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_format_names_what_was_tried() {
+        let e = parse_config(r#"{"channels": "oops"}"#).unwrap_err();
+        let message = e.to_string();
+        assert!(
+            message.starts_with("Not a known config format"),
+            "{}",
+            message
+        );
+        assert!(message.contains("guessed from its fields"), "{}", message);
+    }
+}
@@ -0,0 +1,231 @@
+use anyhow::{Result, anyhow};
+use axum::body::Body;
+use axum::http::HeaderValue;
+use axum::response::Response;
+use futures::stream;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::process::Stdio;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use tokio_util::io::ReaderStream;
+use tracing::info;
+
+use crate::IS_DEV;
+use crate::manifest::FetchError;
+use crate::ytdlp::NetworkOptions;
+
+// Direct URLs stay valid for hours, reusing one spares a yt-dlp call per seek
+const DIRECT_URL_TTL: Duration = Duration::from_secs(30 * 60);
+
+// Response headers passed on from YouTube's servers
+const FORWARDED_HEADERS: [&str; 2] = ["content-length", "content-range"];
+
+/// Where yt-dlp says the chosen format can be downloaded from, and the headers it
+/// would send. Formats merged from separate video and audio have no single URL.
+#[derive(Clone, Deserialize)]
+struct DirectUrl {
+    #[serde(default)]
+    url: Option<String>,
+    #[serde(default)]
+    http_headers: HashMap<String, String>,
+    // The container, e.g. "mp4" or "webm"
+    ext: String,
+}
+
+// Video id and format selector
+type DirectUrlKey = (String, String);
+
+static DIRECT_URLS: LazyLock<Mutex<HashMap<DirectUrlKey, (Instant, DirectUrl)>>> =
+    LazyLock::new(Mutex::default);
+
+// Used when yt-dlp couldn't say which container it picked
+const DEFAULT_EXT: &str = "mp4";
+
+/// The Content-Type to send a video in the `ext` container with.
+fn content_type(ext: &str) -> &'static str {
+    match ext {
+        "mp4" | "m4v" => "video/mp4",
+        "webm" => "video/webm",
+        "mkv" => "video/x-matroska",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Streams a video in `format` (a yt-dlp -f selector, MP4 by default), for when
+/// its HLS manifest can't be used. The file is proxied from its direct URL so
+/// `range` (the client's Range header) is honoured and players can seek. If no
+/// direct URL works, yt-dlp's output is piped through from the start instead,
+/// without seeking. Either way the Content-Type follows the container yt-dlp chose.
+/// Errs when the video can't be streamed at all, with a `FetchError` if yt-dlp
+/// said why.
+pub async fn direct_mp4_streaming(
+    url: &str,
+    video_id: &str,
+    network: &NetworkOptions,
+    format: &str,
+    range: Option<&HeaderValue>,
+) -> Result<Response> {
+    info!("Attempting direct MP4 streaming");
+    let range = range.and_then(|value| value.to_str().ok());
+    let key = (video_id.to_string(), format.to_string());
+
+    let mut ext = DEFAULT_EXT.to_string();
+    match resolve_direct_url(url, &key, network).await {
+        Ok((direct, cached)) => {
+            ext = direct.ext.clone();
+            match proxy_direct_url(&direct, video_id, range, network).await {
+                Ok(response) => return Ok(response),
+                Err(e) => {
+                    info!("Direct MP4 URL for {} failed: {}", video_id, e);
+                    DIRECT_URLS.lock().unwrap().remove(&key);
+                    // A cached URL may just have expired
+                    if cached
+                        && let Ok((direct, _)) = resolve_direct_url(url, &key, network).await
+                        && let Ok(response) =
+                            proxy_direct_url(&direct, video_id, range, network).await
+                    {
+                        return Ok(response);
+                    }
+                }
+            }
+        }
+        // Piping would run into the same refusal, with the client left an empty body
+        Err(e) if e.downcast_ref::<FetchError>().is_some() => return Err(e),
+        Err(e) => info!("No direct MP4 URL for {}: {}", video_id, e),
+    }
+
+    pipe_mp4(url, video_id, format, &ext, network)
+}
+
+/// The direct URL of the format `key` (video id and selector) picks, and whether
+/// it came from the cache.
+async fn resolve_direct_url(
+    url: &str,
+    key: &DirectUrlKey,
+    network: &NetworkOptions,
+) -> Result<(DirectUrl, bool)> {
+    {
+        let mut cache = DIRECT_URLS.lock().unwrap();
+        cache.retain(|_, (resolved, _)| resolved.elapsed() < DIRECT_URL_TTL);
+        if let Some((_, direct)) = cache.get(key) {
+            return Ok((direct.clone(), true));
+        }
+    }
+
+    let output = network
+        .run_with_cookies([
+            "-f",
+            &key.1,
+            "--no-playlist",
+            "--no-warnings",
+            "--print",
+            "%(.{url,http_headers,ext})j",
+            url,
+        ])
+        .await?;
+    if !output.status.success() {
+        if let Some(e) = FetchError::from_stderr(&output.stderr) {
+            return Err(e.into());
+        }
+        return Err(anyhow!(
+            "yt-dlp failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    let direct: DirectUrl = output
+        .stdout
+        .split(|&b| b == b'\n')
+        .find_map(|line| serde_json::from_slice(line).ok())
+        .ok_or_else(|| anyhow!("yt-dlp printed no URL"))?;
+
+    DIRECT_URLS
+        .lock()
+        .unwrap()
+        .insert(key.clone(), (Instant::now(), direct.clone()));
+    Ok((direct, false))
+}
+
+/// Fetches `range` of the MP4 from its direct URL and streams it to the client,
+/// keeping the status (200, 206 or 416) and range headers YouTube answered with.
+async fn proxy_direct_url(
+    direct: &DirectUrl,
+    video_id: &str,
+    range: Option<&str>,
+    network: &NetworkOptions,
+) -> Result<Response> {
+    let url = direct
+        .url
+        .as_ref()
+        .ok_or_else(|| anyhow!("The format is merged from separate streams"))?;
+    let mut request = network.http_client()?.get(url);
+    for (name, value) in &direct.http_headers {
+        request = request.header(name, value);
+    }
+    if let Some(range) = range {
+        request = request.header("range", range);
+    }
+    let upstream = request
+        .send()
+        .await
+        .map_err(|e| anyhow!("Failed to fetch MP4: {}", e))?;
+
+    let status = upstream.status().as_u16();
+    if !matches!(status, 200 | 206 | 416) {
+        return Err(anyhow!("YouTube answered with status {}", status));
+    }
+
+    let mut response = Response::builder()
+        .status(status)
+        .header("Content-Type", content_type(&direct.ext))
+        .header(
+            "Content-Disposition",
+            format!("inline; filename=\"{}.{}\"", video_id, direct.ext),
+        )
+        .header("Accept-Ranges", "bytes")
+        .header("Cache-Control", "no-cache");
+    for name in FORWARDED_HEADERS {
+        if let Some(value) = upstream.headers().get(name) {
+            response = response.header(name, value.as_bytes());
+        }
+    }
+
+    let body = stream::try_unfold(upstream, |mut upstream| async move {
+        Ok::<_, reqwest::Error>(upstream.chunk().await?.map(|chunk| (chunk, upstream)))
+    });
+    Ok(response.body(Body::from_stream(body)).unwrap())
+}
+
+/// Pipes yt-dlp's download of `format` straight to the client, sent as the `ext`
+/// container. Always the whole file, so seeking restarts playback.
+fn pipe_mp4(
+    url: &str,
+    video_id: &str,
+    format: &str,
+    ext: &str,
+    network: &NetworkOptions,
+) -> Result<Response> {
+    // Not counted against the playback slots, it runs for as long as the video plays
+    let process = network
+        .yt_dlp_with_cookies()
+        .args(["-o", "-", "-f", format, "--no-playlist"])
+        .arg(if IS_DEV { "-v" } else { "--no-warnings" })
+        .arg(url)
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| anyhow!("Failed to spawn yt-dlp: {}", e))?;
+
+    let stdout = process.stdout.unwrap();
+    let stream = ReaderStream::new(stdout);
+
+    Ok(Response::builder()
+        .header("Content-Type", content_type(ext))
+        .header(
+            "Content-Disposition",
+            format!("inline; filename=\"{}.{}\"", video_id, ext),
+        )
+        .header("Accept-Ranges", "none")
+        .header("Cache-Control", "no-cache")
+        .body(Body::from_stream(stream))
+        .unwrap())
+}
@@ -0,0 +1,371 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::config::{Channel, Config};
+use crate::ytdlp::NetworkOptions;
+
+// An unreachable webhook shouldn't hold up the next sweep
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Discord rejects messages with more embeds than this
+const DISCORD_MAX_EMBEDS: usize = 10;
+
+// Or with more characters than this across the titles and descriptions of its embeds
+const DISCORD_MAX_EMBED_CHARS: usize = 6000;
+
+// Titles listed per channel before the rest are summed up as "and N more"
+const MAX_LISTED_TITLES: usize = 15;
+
+// Long yt-dlp errors are cut to this many characters
+const MAX_ERROR_CHARS: usize = 1000;
+
+const DISCORD_GREEN: u32 = 0x22c55e;
+const DISCORD_RED: u32 = 0xef4444;
+
+/// How the payload POSTed to `notify_webhook_url` is shaped.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationBackend {
+    /// A JSON array of `{channel, added, error, videos}`
+    #[default]
+    Generic,
+    /// One embed per channel, for Discord webhook URLs
+    Discord,
+    /// A mrkdwn `text` message, for Slack incoming webhooks
+    Slack,
+}
+
+/// A sync worth reporting to `notify_webhook_url`: one that added videos or failed.
+#[derive(Serialize)]
+pub struct SyncNotification {
+    pub channel: String,
+    pub added: usize,
+    pub error: Option<String>,
+    // Titles of the added videos
+    pub videos: Vec<String>,
+    // The channel's avatar, only looked up for Discord
+    #[serde(skip)]
+    pub thumbnail: Option<String>,
+}
+
+static PENDING_NOTIFICATIONS: LazyLock<Mutex<Vec<SyncNotification>>> =
+    LazyLock::new(Mutex::default);
+
+// Channel id to avatar URL, None if YouTube didn't list one
+static AVATARS: LazyLock<Mutex<HashMap<String, Option<String>>>> = LazyLock::new(Mutex::default);
+
+/// The channel's avatar (a playlist's thumbnail) on YouTube, for Discord embeds.
+/// Looked up once per channel and remembered until restart.
+pub async fn channel_avatar(channel: &Channel, settings: &Config) -> Option<String> {
+    if let Some(url) = AVATARS.lock().unwrap().get(&channel.id) {
+        return url.clone();
+    }
+    let network = NetworkOptions::from_config(settings);
+    let url = match channel.get_channel_images(&network).await {
+        Ok(images) => images.poster,
+        Err(e) => {
+            warn!("Failed to look up avatar of {}: {}", channel.get_name(), e);
+            None
+        }
+    };
+    AVATARS
+        .lock()
+        .unwrap()
+        .insert(channel.id.clone(), url.clone());
+    url
+}
+
+/// Holds on to the outcome of `channel`'s sync until `flush_notifications`, if it
+/// added videos or failed. `titles` are those of the added videos.
+pub fn queue_notification(
+    channel: &Channel,
+    result: &Result<usize>,
+    titles: Vec<String>,
+    thumbnail: Option<String>,
+) {
+    let notification = match result {
+        Ok(0) => return,
+        Ok(added) => SyncNotification {
+            channel: channel.get_name().to_string(),
+            added: *added,
+            error: None,
+            videos: titles,
+            thumbnail,
+        },
+        Err(e) => SyncNotification {
+            channel: channel.get_name().to_string(),
+            added: 0,
+            error: Some(format!("{:#}", e)),
+            videos: Vec::new(),
+            thumbnail,
+        },
+    };
+    PENDING_NOTIFICATIONS.lock().unwrap().push(notification);
+}
+
+/// POSTs the queued notifications to `url` through the configured proxy, so a sweep
+/// or a long backfill sends a single request (Discord gets one per message's worth
+/// of embeds). Failures are logged and that request dropped, the others still go out.
+pub async fn flush_notifications(
+    url: &str,
+    backend: NotificationBackend,
+    network: &NetworkOptions,
+) {
+    let batch = std::mem::take(&mut *PENDING_NOTIFICATIONS.lock().unwrap());
+    if batch.is_empty() {
+        return;
+    }
+
+    let client = match network.http_client() {
+        Ok(client) => client,
+        Err(e) => {
+            warn!("Dropping {} sync notifications: {}", batch.len(), e);
+            return;
+        }
+    };
+    let payloads = payloads(backend, &batch);
+    let mut failed = 0;
+    for payload in &payloads {
+        let response = client
+            .post(url)
+            .timeout(WEBHOOK_TIMEOUT)
+            .json(payload)
+            .send()
+            .await;
+        match response {
+            Ok(response) if response.status().is_success() => {}
+            Ok(response) => {
+                warn!("Webhook answered with status {}", response.status());
+                failed += 1;
+            }
+            Err(e) => {
+                warn!("Failed to reach webhook: {}", e);
+                failed += 1;
+            }
+        }
+    }
+    if failed < payloads.len() {
+        info!(
+            "Sent {} of {} webhook requests for {} sync notifications",
+            payloads.len() - failed,
+            payloads.len(),
+            batch.len()
+        );
+    }
+}
+
+/// The request bodies that report `batch` to `backend`.
+fn payloads(backend: NotificationBackend, batch: &[SyncNotification]) -> Vec<Value> {
+    match backend {
+        NotificationBackend::Generic => vec![json!(batch)],
+        NotificationBackend::Discord => {
+            let mut messages: Vec<Vec<Value>> = Vec::new();
+            let mut chars = 0;
+            for embed in batch.iter().map(discord_embed) {
+                let size = embed_chars(&embed);
+                match messages.last_mut() {
+                    Some(embeds)
+                        if embeds.len() < DISCORD_MAX_EMBEDS
+                            && chars + size <= DISCORD_MAX_EMBED_CHARS =>
+                    {
+                        embeds.push(embed);
+                        chars += size;
+                    }
+                    _ => {
+                        messages.push(vec![embed]);
+                        chars = size;
+                    }
+                }
+            }
+            messages
+                .into_iter()
+                .map(|embeds| json!({ "embeds": embeds }))
+                .collect()
+        }
+        NotificationBackend::Slack => {
+            let lines: Vec<String> = batch.iter().map(slack_text).collect();
+            vec![json!({ "text": lines.join("\n\n") })]
+        }
+    }
+}
+
+fn discord_embed(notification: &SyncNotification) -> Value {
+    let mut embed = match &notification.error {
+        Some(error) => json!({
+            "title": format!("{}: sync failed", notification.channel),
+            "description": truncate(error, MAX_ERROR_CHARS),
+            "color": DISCORD_RED,
+        }),
+        None => json!({
+            "title": format!("{}: {}", notification.channel, added_summary(notification.added)),
+            "description": title_list(&notification.videos, "- "),
+            "color": DISCORD_GREEN,
+        }),
+    };
+    if let Some(url) = &notification.thumbnail {
+        embed["thumbnail"] = json!({ "url": url });
+    }
+    embed
+}
+
+/// The characters of `embed` that count towards `DISCORD_MAX_EMBED_CHARS`.
+fn embed_chars(embed: &Value) -> usize {
+    ["title", "description"]
+        .iter()
+        .filter_map(|field| embed[field].as_str())
+        .map(|text| text.chars().count())
+        .sum()
+}
+
+fn slack_text(notification: &SyncNotification) -> String {
+    match &notification.error {
+        Some(error) => format!(
+            "*{}*: sync failed\n```{}```",
+            notification.channel,
+            truncate(error, MAX_ERROR_CHARS)
+        ),
+        None => format!(
+            "*{}*: {}\n{}",
+            notification.channel,
+            added_summary(notification.added),
+            title_list(&notification.videos, "• ")
+        ),
+    }
+}
+
+fn added_summary(added: usize) -> String {
+    if added == 1 {
+        "1 new video".to_string()
+    } else {
+        format!("{} new videos", added)
+    }
+}
+
+/// One title per line after `bullet`, with anything past `MAX_LISTED_TITLES`
+/// summed up on a last line.
+fn title_list(titles: &[String], bullet: &str) -> String {
+    let mut lines: Vec<String> = titles
+        .iter()
+        .take(MAX_LISTED_TITLES)
+        .map(|title| format!("{}{}", bullet, title))
+        .collect();
+    if titles.len() > MAX_LISTED_TITLES {
+        lines.push(format!("and {} more", titles.len() - MAX_LISTED_TITLES));
+    }
+    lines.join("\n")
+}
+
+fn truncate(text: &str, max_chars: usize) -> String {
+    match text.char_indices().nth(max_chars) {
+        Some((end, _)) => format!("{}…", &text[..end]),
+        None => text.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn added(channel: &str, titles: &[&str]) -> SyncNotification {
+        SyncNotification {
+            channel: channel.to_string(),
+            added: titles.len(),
+            error: None,
+            videos: titles.iter().map(|title| title.to_string()).collect(),
+            thumbnail: Some(format!("https://yt3.example/{}.jpg", channel)),
+        }
+    }
+
+    fn failed(channel: &str) -> SyncNotification {
+        SyncNotification {
+            channel: channel.to_string(),
+            added: 0,
+            error: Some("ERROR: [youtube] Sign in to confirm you're not a bot".to_string()),
+            videos: Vec::new(),
+            thumbnail: None,
+        }
+    }
+
+    #[test]
+    fn generic_payload_is_one_array() {
+        let payloads = payloads(
+            NotificationBackend::Generic,
+            &[added("A", &["One", "Two"]), failed("B")],
+        );
+        assert_eq!(
+            payloads,
+            vec![json!([
+                { "channel": "A", "added": 2, "error": null, "videos": ["One", "Two"] },
+                {
+                    "channel": "B",
+                    "added": 0,
+                    "error": "ERROR: [youtube] Sign in to confirm you're not a bot",
+                    "videos": [],
+                },
+            ])]
+        );
+    }
+
+    #[test]
+    fn discord_payload_has_an_embed_per_channel() {
+        let payloads = payloads(
+            NotificationBackend::Discord,
+            &[added("A", &["One", "Two"]), failed("B")],
+        );
+        assert_eq!(
+            payloads,
+            vec![json!({ "embeds": [
+                {
+                    "title": "A: 2 new videos",
+                    "description": "- One\n- Two",
+                    "color": DISCORD_GREEN,
+                    "thumbnail": { "url": "https://yt3.example/A.jpg" },
+                },
+                {
+                    "title": "B: sync failed",
+                    "description": "ERROR: [youtube] Sign in to confirm you're not a bot",
+                    "color": DISCORD_RED,
+                },
+            ]})]
+        );
+    }
+
+    #[test]
+    fn discord_payloads_stay_within_the_message_limits() {
+        let title = "x".repeat(100);
+        let titles = vec![title.as_str(); MAX_LISTED_TITLES];
+        let batch: Vec<_> = (0..25)
+            .map(|i| added(&format!("Channel {}", i), &titles))
+            .collect();
+
+        let payloads = payloads(NotificationBackend::Discord, &batch);
+        let mut sent = 0;
+        for payload in &payloads {
+            let embeds = payload["embeds"].as_array().unwrap();
+            assert!(embeds.len() <= DISCORD_MAX_EMBEDS);
+            assert!(embeds.iter().map(embed_chars).sum::<usize>() <= DISCORD_MAX_EMBED_CHARS);
+            sent += embeds.len();
+        }
+        assert_eq!(sent, batch.len());
+    }
+
+    #[test]
+    fn slack_payload_is_one_mrkdwn_text() {
+        let payloads = payloads(
+            NotificationBackend::Slack,
+            &[added("A", &["One"]), failed("B")],
+        );
+        assert_eq!(
+            payloads,
+            vec![json!({
+                "text": "*A*: 1 new video\n• One\n\n\
+                    *B*: sync failed\n```ERROR: [youtube] Sign in to confirm you're not a bot```"
+            })]
+        );
+    }
+}
@@ -0,0 +1,132 @@
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, Duration as ChronoDuration, Local, TimeZone, Timelike};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// A daily range of local hours, `start_hour` inclusive to `end_hour` exclusive.
+/// Windows may wrap past midnight, e.g. 22-6 covers the night.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct HourWindow {
+    pub start_hour: u32,
+    pub end_hour: u32,
+}
+
+impl HourWindow {
+    pub fn contains_hour(&self, hour: u32) -> bool {
+        if self.start_hour == self.end_hour {
+            // 0-0 and friends mean the whole day
+            true
+        } else if self.start_hour < self.end_hour {
+            hour >= self.start_hour && hour < self.end_hour
+        } else {
+            hour >= self.start_hour || hour < self.end_hour
+        }
+    }
+
+    pub fn contains<Tz: TimeZone>(&self, time: &DateTime<Tz>) -> bool {
+        self.contains_hour(time.hour())
+    }
+
+    /// How long from `now` until the window next opens, zero if it already is.
+    pub fn until_open<Tz: TimeZone>(&self, now: &DateTime<Tz>) -> Duration {
+        if self.contains(now) {
+            return Duration::ZERO;
+        }
+
+        let hours_ahead = (self.start_hour + 24 - now.hour()) % 24;
+        let into_hour = ChronoDuration::minutes(now.minute() as i64)
+            + ChronoDuration::seconds(now.second() as i64);
+        (ChronoDuration::hours(hours_ahead as i64) - into_hour)
+            .to_std()
+            .unwrap_or(Duration::ZERO)
+    }
+
+    pub fn until_open_now(&self) -> Duration {
+        self.until_open(&Local::now())
+    }
+
+    pub fn is_open_now(&self) -> bool {
+        self.contains(&Local::now())
+    }
+}
+
+impl FromStr for HourWindow {
+    type Err = anyhow::Error;
+
+    /// Parses "22-6" style windows.
+    fn from_str(value: &str) -> Result<Self> {
+        let (start, end) = value
+            .split_once('-')
+            .ok_or_else(|| anyhow!("Expected a window like 22-6"))?;
+        let parse_hour = |hour: &str| -> Result<u32> {
+            let hour: u32 = hour
+                .trim()
+                .parse()
+                .map_err(|_| anyhow!("Invalid hour: {}", hour.trim()))?;
+            if hour > 23 {
+                return Err(anyhow!("Hours must be between 0 and 23"));
+            }
+            Ok(hour)
+        };
+        Ok(HourWindow {
+            start_hour: parse_hour(start)?,
+            end_hour: parse_hour(end)?,
+        })
+    }
+}
+
+impl fmt::Display for HourWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.start_hour, self.end_hour)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn at(hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 3, 1, hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn overnight_window_waits_for_its_start() {
+        let window: HourWindow = "22-6".parse().unwrap();
+        assert!(window.contains(&at(23, 0)));
+        assert!(window.contains(&at(3, 59)));
+        assert!(!window.contains(&at(6, 0)));
+        assert!(!window.contains(&at(12, 30)));
+
+        // Checks go ahead inside the window and sleep until 22:00 outside it
+        assert_eq!(window.until_open(&at(2, 0)), Duration::ZERO);
+        assert_eq!(
+            window.until_open(&at(12, 30)),
+            Duration::from_secs((9 * 60 + 30) * 60)
+        );
+        assert_eq!(window.until_open(&at(21, 59)), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn daytime_window_and_whole_day() {
+        let window: HourWindow = "9-17".parse().unwrap();
+        assert!(window.contains(&at(9, 0)));
+        assert!(!window.contains(&at(17, 0)));
+        assert_eq!(
+            window.until_open(&at(18, 0)),
+            Duration::from_secs(15 * 60 * 60)
+        );
+
+        let always: HourWindow = "0-0".parse().unwrap();
+        assert!((0..24).all(|hour| always.contains_hour(hour)));
+    }
+
+    #[test]
+    fn windows_parse_and_print_alike() {
+        assert_eq!("22-6".parse::<HourWindow>().unwrap().to_string(), "22-6");
+        assert!("24-6".parse::<HourWindow>().is_err());
+        assert!("night".parse::<HourWindow>().is_err());
+    }
+}
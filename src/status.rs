@@ -0,0 +1,144 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio_util::sync::CancellationToken;
+
+/// What the background channel checker is doing, as served by `/api/status`.
+/// Times are Unix seconds.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct SweepStatus {
+    pub current_channel: Option<String>,
+    // 1-based position of current_channel among the channels due this sweep
+    pub current_position: usize,
+    pub sweep_size: usize,
+    pub last_sweep_started: Option<u64>,
+    pub last_sweep_finished: Option<u64>,
+    // When the checker wakes up next; None while a sweep is running
+    pub next_sweep: Option<u64>,
+}
+
+#[derive(Default)]
+pub struct SweepTracker {
+    status: Mutex<SweepStatus>,
+}
+
+static SWEEP_TRACKER: LazyLock<SweepTracker> = LazyLock::new(SweepTracker::default);
+
+pub fn sweep_tracker() -> &'static SweepTracker {
+    &SWEEP_TRACKER
+}
+
+impl SweepTracker {
+    pub fn snapshot(&self) -> SweepStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    pub fn sweep_started(&self, sweep_size: usize) {
+        let mut status = self.status.lock().unwrap();
+        status.sweep_size = sweep_size;
+        status.current_position = 0;
+        status.last_sweep_started = Some(unix_secs(SystemTime::now()));
+        status.next_sweep = None;
+    }
+
+    pub fn checking(&self, channel: &str, position: usize) {
+        let mut status = self.status.lock().unwrap();
+        status.current_channel = Some(channel.to_string());
+        status.current_position = position;
+    }
+
+    pub fn sweep_finished(&self) {
+        let mut status = self.status.lock().unwrap();
+        status.current_channel = None;
+        status.current_position = 0;
+        status.last_sweep_finished = Some(unix_secs(SystemTime::now()));
+    }
+
+    /// Whether the checker is partway through a sweep.
+    pub fn is_sweeping(&self) -> bool {
+        self.status.lock().unwrap().current_channel.is_some()
+    }
+
+    /// Records that the checker is sleeping for `wait` before looking again.
+    pub fn sleeping(&self, wait: Duration) {
+        let next = SystemTime::now().checked_add(wait);
+        self.status.lock().unwrap().next_sweep = next.map(unix_secs);
+    }
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Syncs in progress by channel id, so a runaway one can be cancelled from the API.
+#[derive(Default)]
+pub struct ActiveSyncs {
+    syncs: Mutex<HashMap<String, Vec<(u64, CancellationToken)>>>,
+    next_id: AtomicU64,
+}
+
+static ACTIVE_SYNCS: LazyLock<ActiveSyncs> = LazyLock::new(ActiveSyncs::default);
+
+pub fn active_syncs() -> &'static ActiveSyncs {
+    &ACTIVE_SYNCS
+}
+
+/// A registered sync; its token is cancelled by `ActiveSyncs::cancel` or along
+/// with the parent token. Unregisters itself when dropped.
+pub struct ActiveSync {
+    channel_id: String,
+    id: u64,
+    pub token: CancellationToken,
+    // Shutdown or whoever started the sync, as opposed to ActiveSyncs::cancel
+    pub parent: CancellationToken,
+}
+
+impl ActiveSyncs {
+    pub fn register(&self, channel_id: &str, parent: &CancellationToken) -> ActiveSync {
+        let sync = ActiveSync {
+            channel_id: channel_id.to_string(),
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            token: parent.child_token(),
+            parent: parent.clone(),
+        };
+        self.syncs
+            .lock()
+            .unwrap()
+            .entry(sync.channel_id.clone())
+            .or_default()
+            .push((sync.id, sync.token.clone()));
+        sync
+    }
+
+    pub fn is_syncing(&self, channel_id: &str) -> bool {
+        self.syncs.lock().unwrap().contains_key(channel_id)
+    }
+
+    /// Cancels every running sync of `channel_id`. False if none was running.
+    pub fn cancel(&self, channel_id: &str) -> bool {
+        let syncs = self.syncs.lock().unwrap();
+        let Some(tokens) = syncs.get(channel_id) else {
+            return false;
+        };
+        for (_, token) in tokens {
+            token.cancel();
+        }
+        !tokens.is_empty()
+    }
+}
+
+impl Drop for ActiveSync {
+    fn drop(&mut self) {
+        let mut syncs = ACTIVE_SYNCS.syncs.lock().unwrap();
+        if let Some(tokens) = syncs.get_mut(&self.channel_id) {
+            tokens.retain(|(id, _)| *id != self.id);
+            if tokens.is_empty() {
+                syncs.remove(&self.channel_id);
+            }
+        }
+    }
+}
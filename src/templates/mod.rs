@@ -1,6 +1,6 @@
-use std::sync::Arc;
-use minijinja::Environment;
 use anyhow::Result;
+use minijinja::Environment;
+use std::sync::Arc;
 
 pub struct Templates {
     env: Environment<'static>,
@@ -19,4 +19,4 @@ impl Templates {
     }
 }
 
-pub type TemplateState = Arc<Templates>;
\ No newline at end of file
+pub type TemplateState = Arc<Templates>;
@@ -0,0 +1,537 @@
+use anyhow::{Result, anyhow};
+use reqwest::{Client, Proxy};
+use std::ffi::OsStr;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Output, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+use tokio::process::Command;
+use tokio::sync::{OwnedSemaphorePermit, mpsc};
+use tracing::info;
+
+use crate::config::Config;
+use crate::limits::limits;
+
+// Shown when the yt-dlp preflight fails
+pub const YTDLP_INSTALL_HINT: &str = "Install it with `pip install -U yt-dlp` or your package manager \
+    (see https://github.com/yt-dlp/yt-dlp#installation), make sure it is on the PATH of the \
+    user running ytstrm, then restart ytstrm";
+
+/// The installed yt-dlp's version, checked once at startup.
+pub async fn ytdlp_version() -> Result<String> {
+    let output = Command::new("yt-dlp")
+        .arg("--version")
+        .output()
+        .await
+        .map_err(|e| anyhow!("yt-dlp could not be run: {}", e))?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "yt-dlp --version failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+// Shared across invocations so consecutive calls spread over the cookies pool
+static NEXT_COOKIES: AtomicUsize = AtomicUsize::new(0);
+
+/// Network settings shared by every yt-dlp spawn and outgoing HTTP request, so
+/// all YouTube traffic takes the same route.
+#[derive(Debug, Clone, Default)]
+pub struct NetworkOptions {
+    pub proxy_url: Option<String>,
+    pub cookies_pool: Vec<PathBuf>,
+    pub max_concurrent_ytdlp: usize,
+    pub max_concurrent_playback_ytdlp: usize,
+    // Take a playback slot instead of queueing with syncs and maintenance
+    pub playback: bool,
+    // The yt-dlp to run, the one on the PATH unless set (e.g. a fake one in tests)
+    pub program: Option<PathBuf>,
+}
+
+impl NetworkOptions {
+    pub fn from_config(config: &Config) -> Self {
+        NetworkOptions {
+            proxy_url: config.proxy_url.clone(),
+            cookies_pool: config.cookies_pool.clone(),
+            max_concurrent_ytdlp: config.max_concurrent_ytdlp,
+            max_concurrent_playback_ytdlp: config.max_concurrent_playback_ytdlp,
+            playback: false,
+            program: None,
+        }
+    }
+
+    /// The same options for serving a stream, whose yt-dlp runs use the playback slots.
+    pub fn for_playback(&self) -> Self {
+        NetworkOptions {
+            playback: true,
+            ..self.clone()
+        }
+    }
+
+    /// Waits for a yt-dlp slot: a playback one, or one shared with every other
+    /// subsystem. Each yt-dlp run takes its own, held until it exits.
+    async fn slot(&self) -> OwnedSemaphorePermit {
+        if self.playback {
+            return limits()
+                .playback_ytdlp
+                .acquire(self.max_concurrent_playback_ytdlp)
+                .await;
+        }
+        match limits().ytdlp.try_acquire(self.max_concurrent_ytdlp) {
+            Some(permit) => permit,
+            None => {
+                info!("Waiting for other yt-dlp runs to finish");
+                limits().ytdlp.acquire(self.max_concurrent_ytdlp).await
+            }
+        }
+    }
+
+    /// A `yt-dlp` command with the network options applied; callers add the rest.
+    pub fn yt_dlp(&self) -> Command {
+        let mut command = Command::new(self.program.as_deref().unwrap_or(Path::new("yt-dlp")));
+        if let Some(proxy) = &self.proxy_url {
+            command.args(["--proxy", proxy]);
+        }
+        command
+    }
+
+    /// Runs yt-dlp with `args` once a slot is free, without cookies.
+    pub async fn run<I, S>(&self, args: I) -> Result<Output>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let _slot = self.slot().await;
+        self.yt_dlp()
+            .args(args)
+            .kill_on_drop(true)
+            .output()
+            .await
+            .map_err(|e| anyhow!("Failed to execute yt-dlp: {}", e))
+    }
+
+    /// Like `yt_dlp`, signed in with the next cookie file in rotation.
+    pub fn yt_dlp_with_cookies(&self) -> Command {
+        let cookies = self.cookies_rotation().swap_remove(0);
+        let mut command = self.yt_dlp();
+        command.arg("--cookies").arg(cookies);
+        command
+    }
+
+    /// Runs yt-dlp signed in with the next cookie file in rotation. If YouTube rejects
+    /// that account, the remaining files in the pool are tried in turn.
+    pub async fn run_with_cookies<I, S>(&self, args: I) -> Result<Output>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let args: Vec<_> = args
+            .into_iter()
+            .map(|arg| arg.as_ref().to_os_string())
+            .collect();
+        let rotation = self.cookies_rotation();
+        let _slot = self.slot().await;
+
+        let mut output = None;
+        for (attempt, cookies) in rotation.iter().enumerate() {
+            if attempt > 0 {
+                info!("Retrying yt-dlp with cookies from {}", cookies.display());
+            }
+            let result = self
+                .yt_dlp()
+                .arg("--cookies")
+                .arg(cookies)
+                .args(&args)
+                // An abandoned call, e.g. a cancelled scan, shouldn't leave yt-dlp running
+                .kill_on_drop(true)
+                .output()
+                .await
+                .map_err(|e| anyhow!("Failed to execute yt-dlp: {}", e))?;
+
+            let rejected = !result.status.success() && is_auth_failure(&result.stderr);
+            output = Some(result);
+            if !rejected {
+                break;
+            }
+            info!("yt-dlp rejected cookies from {}", cookies.display());
+        }
+
+        output.ok_or_else(|| anyhow!("No cookie files to run yt-dlp with"))
+    }
+
+    /// Like `run_with_cookies`, but parses each line yt-dlp prints as it arrives
+    /// and sends it on to `sink` instead of buffering stdout. yt-dlp is stopped once
+    /// `sink` is closed. `sink` is unbounded so yt-dlp never waits on its reader,
+    /// which may itself be queued for the slot this run holds. A rejected account is only retried with the rest of the pool while
+    /// nothing has been printed yet. The returned stdout is empty unless
+    /// `keep_stdout`, e.g. for a debug log.
+    pub async fn stream_with_cookies<T>(
+        &self,
+        args: &[String],
+        keep_stdout: bool,
+        mut parse: impl FnMut(&[u8]) -> Option<T>,
+        sink: &mpsc::UnboundedSender<T>,
+    ) -> Result<Output> {
+        let rotation = self.cookies_rotation();
+        let _slot = self.slot().await;
+
+        let mut output = None;
+        for (attempt, cookies) in rotation.iter().enumerate() {
+            if attempt > 0 {
+                info!("Retrying yt-dlp with cookies from {}", cookies.display());
+            }
+            let mut child = self
+                .yt_dlp()
+                .arg("--cookies")
+                .arg(cookies)
+                .args(args)
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .kill_on_drop(true)
+                .spawn()
+                .map_err(|e| anyhow!("Failed to execute yt-dlp: {}", e))?;
+            let mut stdout = BufReader::new(child.stdout.take().expect("stdout is piped"));
+            let mut stderr = child.stderr.take().expect("stderr is piped");
+
+            let mut printed = false;
+            let mut kept = Vec::new();
+            let read_stdout = async {
+                let mut line = Vec::new();
+                loop {
+                    line.clear();
+                    if stdout.read_until(b'\n', &mut line).await? == 0 {
+                        return Ok(());
+                    }
+                    if keep_stdout {
+                        kept.extend_from_slice(&line);
+                    }
+                    let line = line.trim_ascii_end();
+                    if line.is_empty() {
+                        continue;
+                    }
+                    printed = true;
+                    let Some(item) = parse(line) else {
+                        continue;
+                    };
+                    if sink.send(item).is_err() {
+                        // Closes stderr too, so the read below finishes
+                        let _ = child.start_kill();
+                        return Ok(());
+                    }
+                }
+            };
+            let mut err = Vec::new();
+            let (read, _) = tokio::join!(read_stdout, stderr.read_to_end(&mut err));
+            read.map_err(|e: std::io::Error| anyhow!("Failed to read yt-dlp output: {}", e))?;
+            let status = child
+                .wait()
+                .await
+                .map_err(|e| anyhow!("Failed to wait for yt-dlp: {}", e))?;
+
+            let rejected = !status.success() && !printed && is_auth_failure(&err);
+            output = Some(Output {
+                status,
+                stdout: kept,
+                stderr: err,
+            });
+            if !rejected {
+                break;
+            }
+            info!("yt-dlp rejected cookies from {}", cookies.display());
+        }
+
+        output.ok_or_else(|| anyhow!("No cookie files to run yt-dlp with"))
+    }
+
+    /// Cookie files to try for one invocation, starting at the next in the pool.
+    /// Without a pool this is the historical cookies.txt in the working directory.
+    fn cookies_rotation(&self) -> Vec<PathBuf> {
+        if self.cookies_pool.is_empty() {
+            return vec![PathBuf::from("cookies.txt")];
+        }
+        let len = self.cookies_pool.len();
+        let start = NEXT_COOKIES.fetch_add(1, Ordering::Relaxed) % len;
+        (0..len)
+            .map(|i| self.cookies_pool[(start + i) % len].clone())
+            .collect()
+    }
+
+    /// The same options with YouTube traffic sent through `proxy` instead.
+    pub fn via_proxy(&self, proxy: &str) -> Self {
+        NetworkOptions {
+            proxy_url: Some(proxy.to_string()),
+            ..self.clone()
+        }
+    }
+
+    pub fn http_client(&self) -> Result<Client> {
+        let mut builder = Client::builder();
+        if let Some(proxy) = &self.proxy_url {
+            let proxy = Proxy::all(proxy).map_err(|e| anyhow!("Invalid proxy URL: {}", e))?;
+            builder = builder.proxy(proxy);
+        }
+        builder
+            .build()
+            .map_err(|e| anyhow!("Failed to build HTTP client: {}", e))
+    }
+}
+
+/// yt-dlp errors for videos YouTube won't serve to the server's region.
+pub fn is_geo_blocked(stderr: &[u8]) -> bool {
+    let stderr = String::from_utf8_lossy(stderr).to_lowercase();
+    GEO_BLOCK_MARKERS
+        .iter()
+        .any(|marker| stderr.contains(marker))
+}
+
+/// Ids of the videos reported as region-blocked in a yt-dlp run's stderr, from
+/// lines like `ERROR: [youtube] <id>: ... not available in your country`.
+pub fn geo_blocked_ids(stderr: &[u8]) -> Vec<String> {
+    String::from_utf8_lossy(stderr)
+        .lines()
+        .filter(|line| is_geo_blocked(line.as_bytes()))
+        .filter_map(|line| {
+            let (_, rest) = line.split_once("[youtube] ")?;
+            let (id, _) = rest.split_once(':')?;
+            Some(id.trim().to_string())
+        })
+        .collect()
+}
+
+const GEO_BLOCK_MARKERS: [&str; 4] = [
+    "not made this video available in your country",
+    "not available in your country",
+    "geo restriction",
+    "geo-restricted",
+];
+
+/// yt-dlp errors for YouTube refusing the server for now, which pass on their own.
+pub fn is_rate_limited(stderr: &[u8]) -> bool {
+    let stderr = String::from_utf8_lossy(stderr).to_lowercase();
+    RATE_LIMIT_MARKERS
+        .iter()
+        .any(|marker| stderr.contains(marker))
+}
+
+const RATE_LIMIT_MARKERS: [&str; 5] = [
+    "http error 429",
+    "too many requests",
+    "rate-limited",
+    "not a bot",
+    "try again later",
+];
+
+/// yt-dlp errors for videos that are gone for everyone: deleted, made private or
+/// from a terminated account. Check `is_rate_limited` first, YouTube words some
+/// rate limiting as "Video unavailable" too.
+pub fn is_removed(stderr: &[u8]) -> bool {
+    let stderr = String::from_utf8_lossy(stderr).to_lowercase();
+    REMOVED_MARKERS.iter().any(|marker| stderr.contains(marker))
+}
+
+const REMOVED_MARKERS: [&str; 6] = [
+    "video unavailable",
+    "has been removed",
+    "private video",
+    "video is private",
+    "account associated with this video has been terminated",
+    "no longer available",
+];
+
+/// Errors that mean the account behind the cookies was refused, rather than the
+/// video being unavailable to everyone.
+fn is_auth_failure(stderr: &[u8]) -> bool {
+    let stderr = String::from_utf8_lossy(stderr);
+    [
+        "Sign in to confirm",
+        "cookies are no longer valid",
+        "HTTP Error 403",
+        "HTTP Error 429",
+        "login required",
+    ]
+    .iter()
+    .any(|marker| stderr.contains(marker))
+}
+
+// A channel's debug log is rotated to <name>.1 once it grows past this
+const MAX_DEBUG_LOG_BYTES: u64 = 5 * 1024 * 1024;
+
+/// Appends one yt-dlp run (its arguments, output and errors) to `path`, keeping
+/// a single older file around when the log gets too big.
+pub fn append_debug_log(path: &Path, args: &[String], output: &Output) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    if std::fs::metadata(path).is_ok_and(|meta| meta.len() > MAX_DEBUG_LOG_BYTES) {
+        let mut rotated = path.as_os_str().to_os_string();
+        rotated.push(".1");
+        std::fs::rename(path, rotated)?;
+    }
+
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    writeln!(
+        file,
+        "=== {} yt-dlp {:?} ({})",
+        chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+        args,
+        output.status
+    )?;
+    file.write_all(b"--- stdout ---\n")?;
+    file.write_all(&output.stdout)?;
+    file.write_all(b"\n--- stderr ---\n")?;
+    file.write_all(&output.stderr)?;
+    file.write_all(b"\n")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn cookies_rotate_across_runs_and_fail_over_in_order() {
+        let network = NetworkOptions {
+            cookies_pool: ["a.txt", "b.txt", "c.txt"].map(PathBuf::from).to_vec(),
+            ..NetworkOptions::default()
+        };
+        let first = network.cookies_rotation();
+        let second = network.cookies_rotation();
+
+        // Each run starts one file further along and falls back through the rest
+        let index = |rotation: &[PathBuf]| {
+            network
+                .cookies_pool
+                .iter()
+                .position(|file| *file == rotation[0])
+                .unwrap()
+        };
+        assert_eq!(index(&second), (index(&first) + 1) % 3);
+        for rotation in [&first, &second] {
+            let start = index(rotation);
+            let expected: Vec<PathBuf> = (0..3)
+                .map(|i| network.cookies_pool[(start + i) % 3].clone())
+                .collect();
+            assert_eq!(*rotation, expected);
+        }
+
+        // Without a pool every run uses cookies.txt
+        assert_eq!(
+            NetworkOptions::default().cookies_rotation(),
+            vec![PathBuf::from("cookies.txt")]
+        );
+    }
+
+    #[test]
+    fn only_rejected_accounts_move_on_to_the_next_cookies() {
+        assert!(is_auth_failure(
+            b"ERROR: [youtube] abc: Sign in to confirm you're not a bot"
+        ));
+        assert!(is_auth_failure(
+            b"ERROR: unable to download: HTTP Error 429"
+        ));
+        assert!(!is_auth_failure(b"ERROR: [youtube] abc: Video unavailable"));
+    }
+
+    #[tokio::test]
+    async fn ytdlp_runs_never_exceed_max_concurrent_ytdlp() {
+        use std::os::unix::fs::PermissionsExt;
+
+        // A fake yt-dlp that lists three videos and logs when it starts and exits
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("runs.log");
+        let program = dir.path().join("yt-dlp");
+        std::fs::write(
+            &program,
+            format!(
+                "#!/bin/sh\necho start >> '{log}'\necho a; echo b; echo c\nsleep 0.1\necho end >> '{log}'\n",
+                log = log.display()
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&program, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let network = NetworkOptions {
+            max_concurrent_ytdlp: 1,
+            program: Some(program),
+            ..NetworkOptions::default()
+        };
+
+        // A streamed scan whose videos each need yt-dlp again, alongside UI lookups
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let scan = async {
+            let args = ["--flat-playlist".to_string()];
+            let parse = |line: &[u8]| Some(line.to_vec());
+            network
+                .stream_with_cookies(&args, false, parse, &tx)
+                .await
+                .unwrap();
+            drop(tx);
+        };
+        let process = async {
+            let mut processed = 0;
+            while rx.recv().await.is_some() {
+                network.run_with_cookies(["--dump-json"]).await.unwrap();
+                processed += 1;
+            }
+            processed
+        };
+        let lookups = futures::future::join_all((0..2).map(|_| network.run(["--version"])));
+        let ((), processed, lookups) = tokio::time::timeout(Duration::from_secs(10), async {
+            tokio::join!(scan, process, lookups)
+        })
+        .await
+        .expect("the scan and its videos don't wait on each other");
+        assert_eq!(processed, 3);
+        assert!(lookups.iter().all(|output| output.is_ok()));
+
+        let mut running = 0;
+        let mut most_running = 0;
+        let log = std::fs::read_to_string(&log).unwrap();
+        for line in log.lines() {
+            running = if line == "start" {
+                running + 1
+            } else {
+                running - 1
+            };
+            most_running = most_running.max(running);
+        }
+        assert_eq!(log.lines().filter(|line| *line == "start").count(), 6);
+        assert_eq!(most_running, 1);
+    }
+
+    #[test]
+    fn debug_log_collects_each_run_and_rotates() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("logs").join("channel.log");
+        let output = std::process::Command::new("sh")
+            .args(["-c", "echo listed; echo '[debug] verbose' >&2"])
+            .output()
+            .unwrap();
+        let args = vec!["-v".to_string(), "--flat-playlist".to_string()];
+
+        append_debug_log(&path, &args, &output).unwrap();
+        append_debug_log(&path, &args, &output).unwrap();
+        let log = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(log.matches("=== ").count(), 2);
+        assert!(log.contains("\"--flat-playlist\""));
+        assert!(log.contains("listed"));
+        assert!(log.contains("[debug] verbose"));
+
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(MAX_DEBUG_LOG_BYTES + 1)
+            .unwrap();
+        append_debug_log(&path, &args, &output).unwrap();
+        assert!(dir.path().join("logs").join("channel.log.1").exists());
+        let log = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(log.matches("=== ").count(), 1);
+    }
+}